@@ -11,6 +11,9 @@ use std::io;
 
 #[macro_use]
 pub mod tablumps;
+pub mod names;
+
+pub use names::{ChannelName,Username};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct AsciiBytes(Vec<u8>);
@@ -87,6 +90,16 @@ impl MessageBody {
     pub fn to_string(&self) -> String {
         self.0.decode()
     }
+
+    /// The body exactly as it goes over the wire, terminator included.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    /// Wraps already-encoded bytes; no escaping is done.
+    pub fn from_raw(bytes: Vec<u8>) -> Self {
+        MessageBody(AsciiBytes(bytes))
+    }
 }
 
 impl<'a> From<&'a [u8]> for Message {
@@ -385,3 +398,10 @@ fn parse_lumps() {
     let body = Message::from("foo\n\nfoo &amp; &b\thello, world&/b\t\0").body.expect("no body");
     assert_eq!(body.to_string(), "foo & <b>hello, world</b>");
 }
+
+#[test]
+fn body_raw_roundtrip() {
+    let body = MessageBody::from("a & b \u{1f370}".to_string());
+    assert_eq!(body.as_bytes(), &b"a &amp; b &#x1f370;"[..]);
+    assert_eq!(MessageBody::from_raw(body.as_bytes().to_vec()), body);
+}
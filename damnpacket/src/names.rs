@@ -0,0 +1,227 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash,Hasher};
+use std::str::FromStr;
+
+/// A dAmn username. Compares, hashes and sorts case-insensitively, as the
+/// server does, but keeps the casing it was given for display.
+#[derive(Clone, Debug)]
+pub struct Username(String);
+
+impl Username {
+    pub fn new<S>(s: S) -> Self
+        where S: Into<String> {
+        Username(s.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    fn folded(&self) -> String {
+        self.0.to_lowercase()
+    }
+}
+
+impl PartialEq for Username {
+    fn eq(&self, other: &Username) -> bool {
+        self.folded() == other.folded()
+    }
+}
+
+impl Eq for Username {}
+
+impl<'a> PartialEq<&'a str> for Username {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.folded() == other.to_lowercase()
+    }
+}
+
+impl Hash for Username {
+    fn hash<H>(&self, state: &mut H)
+        where H: Hasher {
+        self.folded().hash(state)
+    }
+}
+
+impl PartialOrd for Username {
+    fn partial_cmp(&self, other: &Username) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Username {
+    fn cmp(&self, other: &Username) -> Ordering {
+        self.folded().cmp(&other.folded())
+    }
+}
+
+impl fmt::Display for Username {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'a> From<&'a str> for Username {
+    fn from(s: &'a str) -> Self {
+        Username(s.to_string())
+    }
+}
+
+impl From<String> for Username {
+    fn from(s: String) -> Self {
+        Username(s)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Username {
+    fn from(s: &'a [u8]) -> Self {
+        Username(string!(s))
+    }
+}
+
+/// A dAmn namespace: a public `chat:` room or a `pchat:` between two users.
+/// The users in a pchat are kept in the canonical (case-insensitive,
+/// alphabetical) order, which is the only one the server accepts. Like
+/// usernames, room names compare case-insensitively.
+#[derive(Clone, Debug)]
+pub enum ChannelName {
+    Chat(String),
+    Private(Username, Username),
+}
+
+impl ChannelName {
+    /// Parses the wire form, `chat:name` or `pchat:a:b`.
+    pub fn parse(s: &[u8]) -> Option<ChannelName> {
+        let s = string!(s);
+        if s.starts_with("chat:") {
+            let name = &s[5..];
+            if name.is_empty() || name.contains(':') {
+                return None
+            }
+            Some(ChannelName::Chat(name.to_string()))
+        } else if s.starts_with("pchat:") {
+            let users = (&s[6..]).split(':').collect::<Vec<_>>();
+            if users.len() != 2 || users.iter().any(|u| u.is_empty()) {
+                return None
+            }
+            Some(ChannelName::private(Username::from(users[0]), Username::from(users[1])))
+        } else {
+            None
+        }
+    }
+
+    pub fn private(a: Username, b: Username) -> ChannelName {
+        if a <= b {
+            ChannelName::Private(a, b)
+        } else {
+            ChannelName::Private(b, a)
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        match *self {
+            ChannelName::Private(..) => true,
+            ChannelName::Chat(_) => false,
+        }
+    }
+
+    /// In a pchat, whoever isn't `me`.
+    pub fn other(&self, me: &Username) -> Option<&Username> {
+        match *self {
+            ChannelName::Private(ref a, ref b) => Some(if a == me { b } else { a }),
+            ChannelName::Chat(_) => None,
+        }
+    }
+
+    /// `#name` for rooms, the full form for pchats.
+    pub fn shorthand(&self) -> String {
+        match *self {
+            ChannelName::Chat(ref n) => format!("#{}", n),
+            ChannelName::Private(..) => self.to_string(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    fn folded(&self) -> String {
+        self.to_string().to_lowercase()
+    }
+}
+
+impl PartialEq for ChannelName {
+    fn eq(&self, other: &ChannelName) -> bool {
+        self.folded() == other.folded()
+    }
+}
+
+impl Eq for ChannelName {}
+
+impl Hash for ChannelName {
+    fn hash<H>(&self, state: &mut H)
+        where H: Hasher {
+        self.folded().hash(state)
+    }
+}
+
+impl fmt::Display for ChannelName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ChannelName::Chat(ref n) => write!(f, "chat:{}", n),
+            ChannelName::Private(ref a, ref b) => write!(f, "pchat:{}:{}", a, b),
+        }
+    }
+}
+
+/// Accepts the wire forms as well as the `#name` shorthand people type.
+impl FromStr for ChannelName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s.starts_with('#') && s.len() > 1 && !s.contains(':') {
+            return Ok(ChannelName::Chat(s[1..].to_string()))
+        }
+        ChannelName::parse(s.as_bytes()).ok_or(format!("'{}' isn't a room name", s))
+    }
+}
+
+#[test]
+fn username_case() {
+    assert_eq!(Username::from("Alice"), Username::from("aLICE"));
+    assert_eq!(Username::from("Alice"), "alice");
+    assert_eq!(Username::from("Alice").to_string(), "Alice");
+    assert!(Username::from("alice") < Username::from("Bob"));
+}
+
+#[test]
+fn channel_parse() {
+    assert_eq!(ChannelName::parse(b"chat:Botdom"), Some(ChannelName::Chat("Botdom".to_string())));
+    assert_eq!(ChannelName::parse(b"pchat:zed:Amy").map(|c| c.to_string()), Some("pchat:Amy:zed".to_string()));
+    assert_eq!(ChannelName::parse(b"pchat:amy"), None);
+    assert_eq!(ChannelName::parse(b"chat:"), None);
+    assert_eq!(ChannelName::parse(b"Botdom"), None);
+}
+
+#[test]
+fn channel_shorthand() {
+    assert_eq!("#botdom".parse::<ChannelName>(), Ok(ChannelName::Chat("Botdom".to_string())));
+    assert_eq!(ChannelName::Chat("Botdom".to_string()).shorthand(), "#Botdom");
+    assert!("#".parse::<ChannelName>().is_err());
+    assert!("botdom".parse::<ChannelName>().is_err());
+}
+
+#[test]
+fn channel_private() {
+    let me = Username::from("participle");
+    let room = ChannelName::private(me.clone(), Username::from("Alice"));
+    assert_eq!(room.to_string(), "pchat:Alice:participle");
+    assert_eq!(room, ChannelName::private(Username::from("alice"), Username::from("Participle")));
+    assert_eq!(room.other(&me), Some(&Username::from("alice")));
+    assert!(room.is_private());
+}
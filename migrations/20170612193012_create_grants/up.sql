@@ -0,0 +1,8 @@
+CREATE TABLE grants (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user VARCHAR NOT NULL,
+    channel VARCHAR NOT NULL,
+    name VARCHAR NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS UniqueGrant ON grants (user, channel, name);
@@ -0,0 +1,2 @@
+DROP TABLE triggers;
+DROP TABLE disabled_commands;
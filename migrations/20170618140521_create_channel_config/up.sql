@@ -0,0 +1,15 @@
+CREATE TABLE triggers (
+    id INTEGER PRIMARY KEY NOT NULL,
+    channel VARCHAR NOT NULL,
+    prefix VARCHAR NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS UniqueTrigger ON triggers (channel, prefix);
+
+CREATE TABLE disabled_commands (
+    id INTEGER PRIMARY KEY NOT NULL,
+    channel VARCHAR NOT NULL,
+    name VARCHAR NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS UniqueDisabledCommand ON disabled_commands (channel, name);
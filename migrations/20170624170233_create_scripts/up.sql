@@ -0,0 +1,19 @@
+CREATE TABLE scripts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    channel VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    code TEXT NOT NULL,
+    author VARCHAR NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS UniqueScript ON scripts (channel, name);
+
+CREATE TABLE script_data (
+    id INTEGER PRIMARY KEY NOT NULL,
+    channel VARCHAR NOT NULL,
+    script VARCHAR NOT NULL,
+    key VARCHAR NOT NULL,
+    value TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS UniqueScriptData ON script_data (channel, script, key);
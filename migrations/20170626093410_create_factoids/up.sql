@@ -0,0 +1,9 @@
+CREATE TABLE factoids (
+    id INTEGER PRIMARY KEY NOT NULL,
+    channel VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    body TEXT NOT NULL,
+    author VARCHAR NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS FactoidName ON factoids (name);
@@ -0,0 +1 @@
+-- The original case is gone, so there's nothing to undo.
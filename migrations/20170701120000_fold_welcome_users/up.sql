@@ -0,0 +1,2 @@
+-- Welcomes used to keep the case the user typed; fold them like grants.
+UPDATE OR REPLACE welcomes SET user = lower(user);
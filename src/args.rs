@@ -0,0 +1,456 @@
+use damnpacket::{ChannelName,Username};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// What an argument has to look like.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Word, Int, Duration, User, Room,
+    /// A word, or several in double quotes.
+    Quoted,
+}
+
+impl Kind {
+    fn parse(s: &str) -> Result<Kind, String> {
+        match s {
+            "word" => Ok(Kind::Word),
+            "int" => Ok(Kind::Int),
+            "duration" => Ok(Kind::Duration),
+            "user" => Ok(Kind::User),
+            "room" => Ok(Kind::Room),
+            "quoted" => Ok(Kind::Quoted),
+            k => Err(format!("unknown kind '{}'", k)),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match *self {
+            Kind::Word | Kind::Quoted => "a word",
+            Kind::Int => "a number",
+            Kind::Duration => "a duration like 90s or 1h30m, up to 30 days",
+            Kind::User => "a username",
+            Kind::Room => "a room like #botdom",
+        }
+    }
+
+    fn value(&self, s: &str) -> Option<Value> {
+        match *self {
+            Kind::Word | Kind::Quoted => Some(Value::Text(s.to_string())),
+            Kind::Int => s.parse().ok().map(Value::Int),
+            Kind::Duration => duration(s).map(Value::Duration),
+            Kind::User => if s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+                Some(Value::User(Username::from(s)))
+            } else {
+                None
+            },
+            Kind::Room => s.parse().ok().map(Value::Room),
+        }
+    }
+}
+
+/// The longest `duration` will accept, so nothing schedules past what an
+/// `Instant` can hold.
+pub const MAX_DURATION: u64 = 30 * 86400;
+
+/// `90` (seconds), `90s`, `5m`, `1h30m`, `2d`; no more than `MAX_DURATION`.
+pub fn duration(s: &str) -> Option<Duration> {
+    if let Ok(n) = s.parse() {
+        return if n <= MAX_DURATION { Some(Duration::from_secs(n)) } else { None }
+    }
+
+    let mut total: u64 = 0;
+    let mut n = String::new();
+    for c in s.chars() {
+        if c.is_digit(10) {
+            n.push(c);
+            continue
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return None,
+        };
+        match n.parse::<u64>().ok()
+            .and_then(|x| x.checked_mul(unit))
+            .and_then(|x| x.checked_add(total)) {
+            Some(t) if t <= MAX_DURATION => total = t,
+            _ => return None,
+        }
+        n.clear();
+    }
+    if !n.is_empty() || s.is_empty() {
+        return None
+    }
+    Some(Duration::from_secs(total))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Literal { word: String, optional: bool },
+    Arg { name: String, kind: Kind, optional: bool, rest: bool },
+}
+
+impl Item {
+    /// `word`, `[word]`, `<name>`, `<name:kind>`, `[<name:kind>]` or
+    /// `<name...>` for the rest of the line.
+    fn parse(tok: &str) -> Result<Item, String> {
+        let (tok, optional) = if tok.starts_with('[') && tok.ends_with(']') {
+            (&tok[1..tok.len() - 1], true)
+        } else {
+            (tok, false)
+        };
+
+        if !(tok.starts_with('<') && tok.ends_with('>')) {
+            if tok.is_empty() || tok.contains(|c: char| "<>[]:".contains(c)) {
+                return Err(format!("bad literal '{}'", tok))
+            }
+            return Ok(Item::Literal { word: tok.to_lowercase(), optional: optional })
+        }
+
+        let inner = &tok[1..tok.len() - 1];
+        let (inner, rest) = if inner.ends_with("...") {
+            (&inner[..inner.len() - 3], true)
+        } else {
+            (inner, false)
+        };
+        let (name, kind) = match inner.find(':') {
+            Some(i) => (&inner[..i], Kind::parse(&inner[i + 1..])?),
+            None => (inner, Kind::Word),
+        };
+        if name.is_empty() {
+            return Err(format!("no name in '{}'", tok))
+        }
+        if rest && kind != Kind::Word {
+            return Err(format!("'{}' takes the rest of the line, so it can't have a kind", tok))
+        }
+        Ok(Item::Arg { name: name.to_string(), kind: kind, optional: optional, rest: rest })
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let (s, optional) = match *self {
+            Item::Literal { ref word, optional } => (word.clone(), optional),
+            Item::Arg { ref name, optional, rest, .. } =>
+                (format!("<i>{}</i>{}", name, if rest { "..." } else { "" }), optional),
+        };
+        if optional {
+            write!(f, "[{}]", s)
+        } else {
+            f.write_str(&s)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Int(i64),
+    Duration(Duration),
+    User(Username),
+    Room(ChannelName),
+}
+
+/// What a command was given, by the names in its declaration.
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    sub: String,
+    flags: Vec<String>,
+    values: HashMap<String, Value>,
+}
+
+impl Args {
+    /// The required literals that were matched, like `clear user`.
+    pub fn sub(&self) -> &str {
+        &self.sub
+    }
+
+    /// Whether an optional literal like `[everywhere]` was given.
+    pub fn has(&self, word: &str) -> bool {
+        self.flags.iter().any(|f| f == word)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    pub fn str(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(&Value::Text(ref s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(&Value::Int(n)) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self, name: &str) -> Option<Duration> {
+        match self.get(name) {
+            Some(&Value::Duration(d)) => Some(d),
+            _ => None,
+        }
+    }
+
+    pub fn user(&self, name: &str) -> Option<&Username> {
+        match self.get(name) {
+            Some(&Value::User(ref u)) => Some(u),
+            _ => None,
+        }
+    }
+
+    pub fn room(&self, name: &str) -> Option<&ChannelName> {
+        match self.get(name) {
+            Some(&Value::Room(ref r)) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgError {
+    Missing(String),
+    Invalid { name: String, kind: Kind, value: String },
+    Unexpected(String),
+    Unterminated,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ArgError::Missing(ref name) => write!(f, "Missing <i>{}</i>.", name),
+            ArgError::Invalid { ref name, kind, ref value } =>
+                write!(f, "'{}' isn't {} (for <i>{}</i>).", value, kind.describe(), name),
+            ArgError::Unexpected(ref s) => write!(f, "I don't know what to do with '{}'.", s),
+            ArgError::Unterminated => write!(f, "There's a quote without an end."),
+        }
+    }
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_left()),
+        None => (s, ""),
+    }
+}
+
+/// A `"quoted string"`, with `\"` for quotes inside it, or else one word.
+fn split_quoted(s: &str) -> Result<(String, &str), ArgError> {
+    if !s.starts_with('"') {
+        let (w, rest) = split_word(s);
+        return Ok((w.to_string(), rest))
+    }
+
+    let mut out = String::new();
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match (escaped, c) {
+            (false, '\\') => escaped = true,
+            (false, '"') => return Ok((out, s[i + 1..].trim_left())),
+            (_, c) => {
+                out.push(c);
+                escaped = false;
+            }
+        }
+    }
+    Err(ArgError::Unterminated)
+}
+
+/// A command's declared arguments: alternatives separated by `|`, each a
+/// sequence of literals and placeholders, as in
+/// `[list] | cancel <id:int> | every <minutes:int> <message...>`.
+/// Displays as the usage line shown to people.
+#[derive(Clone, Debug)]
+pub struct Syntax(Vec<Vec<Item>>);
+
+impl Syntax {
+    pub fn parse(decl: &str) -> Result<Syntax, String> {
+        decl.split('|').map(|alt|
+            alt.split_whitespace().map(Item::parse).collect::<Result<Vec<_>, _>>()
+        ).collect::<Result<Vec<_>, _>>().map(Syntax)
+    }
+
+    /// Tries each alternative in turn. If none fits, the error comes from
+    /// the one that got furthest.
+    pub fn apply(&self, input: &str) -> Result<Args, ArgError> {
+        let input = input.trim();
+        let mut best: Option<(usize, ArgError)> = None;
+        for alt in self.0.iter() {
+            match apply_alt(alt, input) {
+                Ok(args) => return Ok(args),
+                Err((n, e)) => if best.as_ref().map(|b| n > b.0).unwrap_or(true) {
+                    best = Some((n, e))
+                },
+            }
+        }
+
+        match best {
+            Some((0, _)) if self.0.len() > 1 => Err(match split_word(input).0 {
+                "" => ArgError::Missing("subcommand".to_string()),
+                w => ArgError::Unexpected(w.to_string()),
+            }),
+            Some((_, e)) => Err(e),
+            None => Err(ArgError::Unexpected(input.to_string())),
+        }
+    }
+}
+
+/// On failure, also says how many literals matched first.
+fn apply_alt(items: &[Item], mut input: &str) -> Result<Args, (usize, ArgError)> {
+    let mut args = Args::default();
+    let mut sub = vec![];
+    let mut matched = 0;
+
+    for item in items.iter() {
+        match *item {
+            Item::Literal { ref word, optional } => {
+                let (tok, after) = split_word(input);
+                if tok.to_lowercase() == *word {
+                    input = after;
+                    matched += 1;
+                    if optional {
+                        args.flags.push(word.clone());
+                    } else {
+                        sub.push(word.clone());
+                    }
+                } else if !optional {
+                    return Err((matched, match tok {
+                        "" => ArgError::Missing(word.clone()),
+                        t => ArgError::Unexpected(t.to_string()),
+                    }))
+                }
+            },
+            Item::Arg { ref name, kind, optional, rest } => {
+                if input.is_empty() {
+                    if optional {
+                        continue
+                    }
+                    return Err((matched, ArgError::Missing(name.clone())))
+                }
+                if rest {
+                    args.values.insert(name.clone(), Value::Text(input.to_string()));
+                    input = "";
+                    continue
+                }
+
+                let (tok, after) = if kind == Kind::Quoted {
+                    split_quoted(input).map_err(|e| (matched, e))?
+                } else {
+                    let (w, after) = split_word(input);
+                    (w.to_string(), after)
+                };
+                match kind.value(&tok) {
+                    Some(v) => { args.values.insert(name.clone(), v); },
+                    None => return Err((matched, ArgError::Invalid {
+                        name: name.clone(),
+                        kind: kind,
+                        value: tok,
+                    })),
+                }
+                input = after;
+            },
+        }
+    }
+
+    if !input.is_empty() {
+        return Err((matched, ArgError::Unexpected(input.to_string())))
+    }
+    args.sub = sub.join(" ");
+    Ok(args)
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let alts = self.0.iter().map(|alt|
+            alt.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(" ")
+        ).collect::<Vec<_>>();
+        if alts.len() > 1 {
+            write!(f, "{{ {} }}", alts.join(" | "))
+        } else {
+            f.write_str(&alts.join(""))
+        }
+    }
+}
+
+#[cfg(test)]
+fn syntax(decl: &str) -> Syntax {
+    Syntax::parse(decl).unwrap()
+}
+
+#[test]
+fn args_positional() {
+    let s = syntax("<user:user> [<reason...>]");
+    let a = s.apply("Alice  go away now ").unwrap();
+    assert_eq!(a.user("user"), Some(&Username::from("alice")));
+    assert_eq!(a.str("reason"), Some("go away now"));
+    assert_eq!(s.apply("alice").unwrap().str("reason"), None);
+    assert_eq!(s.apply("").unwrap_err(), ArgError::Missing("user".to_string()));
+    assert_eq!(s.apply("a<b").unwrap_err(), ArgError::Invalid {
+        name: "user".to_string(), kind: Kind::User, value: "a<b".to_string() });
+}
+
+#[test]
+fn args_kinds() {
+    let s = syntax("<n:int> <d:duration> <r:room> <q:quoted>");
+    let a = s.apply("-3 1h30m #Botdom \"two \\\"words\\\"\"").unwrap();
+    assert_eq!(a.int("n"), Some(-3));
+    assert_eq!(a.duration("d"), Some(Duration::from_secs(5400)));
+    assert_eq!(a.room("r"), Some(&ChannelName::Chat("botdom".to_string())));
+    assert_eq!(a.str("q"), Some("two \"words\""));
+    assert_eq!(s.apply("1 2 #a \"open").unwrap_err(), ArgError::Unterminated);
+    assert_eq!(s.apply("x 2 #a b").unwrap_err().to_string(), "'x' isn't a number (for <i>n</i>).");
+    assert_eq!(s.apply("1 2 #a b c").unwrap_err(), ArgError::Unexpected("c".to_string()));
+    assert_eq!(s.apply("1 18446744073709551615 #a b").unwrap_err(), ArgError::Invalid {
+        name: "d".to_string(), kind: Kind::Duration, value: "18446744073709551615".to_string() });
+}
+
+#[test]
+fn args_durations() {
+    assert_eq!(duration("90"), Some(Duration::from_secs(90)));
+    assert_eq!(duration("2m5s"), Some(Duration::from_secs(125)));
+    assert_eq!(duration("1d"), Some(Duration::from_secs(86400)));
+    assert_eq!(duration("5"), Some(Duration::from_secs(5)));
+    assert_eq!(duration("m"), None);
+    assert_eq!(duration("5x"), None);
+    assert_eq!(duration("5m3"), None);
+    assert_eq!(duration(""), None);
+    assert_eq!(duration("30d"), Some(Duration::from_secs(MAX_DURATION)));
+    assert_eq!(duration("31d"), None);
+    assert_eq!(duration("18446744073709551615"), None);
+    assert_eq!(duration("18446744073709551615s"), None);
+    assert_eq!(duration("9999999999999999h"), None);
+}
+
+#[test]
+fn args_subcommands() {
+    let s = syntax("[list] | cancel <id:int> | clear here | clear user <name:user> | every <minutes:int> <message...>");
+    assert_eq!(s.apply("").unwrap().sub(), "");
+    assert_eq!(s.apply("list").unwrap().sub(), "");
+    assert!(s.apply("list").unwrap().has("list"));
+    assert_eq!(s.apply("CANCEL 3").unwrap().int("id"), Some(3));
+    assert_eq!(s.apply("clear here").unwrap().sub(), "clear here");
+    assert_eq!(s.apply("clear user bob").unwrap().sub(), "clear user");
+    let a = s.apply("every 5 drink water").unwrap();
+    assert_eq!((a.sub(), a.int("minutes"), a.str("message")), ("every", Some(5), Some("drink water")));
+
+    assert_eq!(s.apply("dance").unwrap_err(), ArgError::Unexpected("dance".to_string()));
+    assert_eq!(s.apply("cancel").unwrap_err(), ArgError::Missing("id".to_string()));
+    assert_eq!(s.apply("clear user").unwrap_err(), ArgError::Missing("name".to_string()));
+    assert_eq!(syntax("on | off").apply("").unwrap_err(), ArgError::Missing("subcommand".to_string()));
+}
+
+#[test]
+fn args_usage() {
+    assert_eq!(syntax("<user:user> [<reason...>]").to_string(), "<i>user</i> [<i>reason</i>...]");
+    assert_eq!(syntax("<user> <what> [everywhere]").to_string(), "<i>user</i> <i>what</i> [everywhere]");
+    assert_eq!(syntax("on | off").to_string(), "{ on | off }");
+    assert!(Syntax::parse("<x:float>").is_err());
+    assert!(Syntax::parse("<>").is_err());
+    assert!(Syntax::parse("<rest:int...>").is_err());
+}
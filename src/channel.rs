@@ -0,0 +1,258 @@
+use damnpacket::{self,ChannelName,Message,MessageIsh,SubMessage,Username};
+use std::cell::RefCell;
+use std::collections::{BTreeMap,HashMap};
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub name: Username,
+    pub privclass: String,
+    pub symbol: String,
+    pub realname: String,
+    /// dAmn lists a user once per connection; they're only gone once all part.
+    pub connections: usize,
+}
+
+impl Member {
+    fn from_attrs<M>(name: Username, m: &M) -> Self
+        where M: MessageIsh {
+        Member {
+            name: name,
+            privclass: m.get_attr("pc").unwrap_or("").to_string(),
+            symbol: m.get_attr("symbol").unwrap_or("").to_string(),
+            realname: m.get_attr("realname").unwrap_or("").to_string(),
+            connections: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Channel {
+    members: BTreeMap<Username, Member>,
+    privclasses: Vec<(u32, String)>,
+    pub topic: String,
+    pub title: String,
+}
+
+impl Channel {
+    pub fn members(&self) -> Vec<&Member> {
+        self.members.values().collect()
+    }
+
+    pub fn member(&self, name: &Username) -> Option<&Member> {
+        self.members.get(name)
+    }
+
+    /// Privclasses from highest to lowest order.
+    pub fn privclasses(&self) -> &[(u32, String)] {
+        &self.privclasses
+    }
+
+    pub fn privclass_order(&self, pc: &str) -> Option<u32> {
+        self.privclasses.iter().find(|x| x.1 == pc).map(|x| x.0)
+    }
+
+    fn join(&mut self, m: Member) {
+        if let Some(existing) = self.members.get_mut(&m.name) {
+            existing.connections += 1;
+            return
+        }
+        self.members.insert(m.name.clone(), m);
+    }
+
+    fn part(&mut self, name: &Username) {
+        let gone = match self.members.get_mut(name) {
+            Some(m) => {
+                m.connections = m.connections.saturating_sub(1);
+                m.connections == 0
+            },
+            None => false
+        };
+        if gone {
+            self.members.remove(name);
+        }
+    }
+}
+
+/// Splits a `property p=members` body into one block per `member` line.
+fn member_blocks(body: &[u8]) -> Vec<Message> {
+    let body = match body.last() {
+        Some(&0) => &body[..body.len() - 1],
+        _ => body
+    };
+    let mut out = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while i <= body.len() {
+        if i == body.len() || (i + 1 < body.len() && &body[i..i + 2] == b"\n\n") {
+            let chunk = &body[start..i];
+            if !chunk.iter().all(|&c| c == b'\n') {
+                let mut raw = chunk.to_vec();
+                raw.extend(b"\n\0");
+                match damnpacket::parse(&raw) {
+                    Ok(m) => out.push(m),
+                    Err(e) => warn!("bad member block {:?}: {:?}", string!(chunk), e)
+                }
+            }
+            start = i + 2;
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    out
+}
+
+fn parse_privclasses(text: &str) -> Vec<(u32, String)> {
+    let mut pcs = text.lines().filter_map(|l| {
+        let mut parts = l.splitn(2, ':');
+        match (parts.next().and_then(|n| n.parse::<u32>().ok()), parts.next()) {
+            (Some(n), Some(name)) => Some((n, name.to_string())),
+            _ => None
+        }
+    }).collect::<Vec<_>>();
+    pcs.sort_by(|a, b| b.0.cmp(&a.0));
+    pcs
+}
+
+/// Everything we know about the rooms we're in, shared by every `Event`.
+#[derive(Clone)]
+pub struct Channels(Rc<RefCell<HashMap<ChannelName, Channel>>>);
+
+impl Channels {
+    pub fn new() -> Self {
+        Channels(Rc::new(RefCell::new(HashMap::new())))
+    }
+
+    pub fn get(&self, room: &ChannelName) -> Option<Channel> {
+        self.0.borrow().get(room).cloned()
+    }
+
+    pub fn forget(&self, room: &ChannelName) {
+        self.0.borrow_mut().remove(room);
+    }
+
+    /// Updates state from a `property` or `recv` packet; anything else is ignored.
+    pub fn observe(&self, msg: &Message) {
+        let room = match msg.argument.as_ref().and_then(|r| ChannelName::parse(r)) {
+            Some(r) => r,
+            None => return
+        };
+        let mut map = self.0.borrow_mut();
+        match &msg.name[..] {
+            b"property" => {
+                let ch = map.entry(room).or_insert_with(Channel::default);
+                let text = msg.body.as_ref().map(|b| b.to_string()).unwrap_or(String::new());
+                match msg.get_attr("p") {
+                    Some("members") => {
+                        ch.members.clear();
+                        let raw = msg.body.as_ref().map(|b| b.as_bytes()).unwrap_or(&[]);
+                        for block in member_blocks(raw) {
+                            if let Some(ref name) = block.argument {
+                                ch.join(Member::from_attrs(Username::from(&name[..]), &block));
+                            }
+                        }
+                    },
+                    Some("privclasses") => ch.privclasses = parse_privclasses(&text),
+                    Some("topic") => ch.topic = text,
+                    Some("title") => ch.title = text,
+                    _ => ()
+                }
+            },
+            b"recv" => {
+                let ch = match map.get_mut(&room) {
+                    Some(ch) => ch,
+                    None => return
+                };
+                if let Some(sub) = msg.submessage() {
+                    let user = match sub.argument {
+                        Some(ref u) => Username::from(&u[..]),
+                        None => return
+                    };
+                    match sub.name.as_ref().map(|x|x.as_slice()) {
+                        Some(b"join") => {
+                            let info = sub.body.as_ref().and_then(|b| b.submessage().ok());
+                            ch.join(match info {
+                                Some(ref i) => Member::from_attrs(user, i),
+                                None => Member::from_attrs(user, &SubMessage {
+                                    name: None, argument: None, attrs: HashMap::new(), body: None
+                                })
+                            });
+                        },
+                        Some(b"part") => ch.part(&user),
+                        Some(b"kicked") => { ch.members.remove(&user); },
+                        Some(b"privchg") => {
+                            if let (Some(m), Some(pc)) = (ch.members.get_mut(&user), sub.get_attr("pc")) {
+                                m.privclass = pc.to_string();
+                            }
+                        },
+                        _ => ()
+                    }
+                }
+            },
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+fn room() -> ChannelName {
+    ChannelName::Chat("room".to_string())
+}
+
+#[cfg(test)]
+static MEMBERS: &'static str = "property chat:room\np=members\n\n\
+    member alice\npc=Operators\nusericon=1\nsymbol=~\nrealname=Alice\ntypename=x\ngpc=guest\n\n\
+    member bob\npc=Members\nusericon=0\nsymbol=~\nrealname=\ntypename=x\ngpc=guest\n\n\
+    member bob\npc=Members\nusericon=0\nsymbol=~\nrealname=\ntypename=x\ngpc=guest\n\n\0";
+
+#[cfg(test)]
+static PRIVCLASSES: &'static str = "property chat:room\np=privclasses\n\n\
+    99:Founders\n75:Operators\n50:Members\n25:Guests\n\0";
+
+#[test]
+fn channel_members() {
+    let c = Channels::new();
+    c.observe(&Message::from(MEMBERS));
+    let ch = c.get(&room()).unwrap();
+    assert_eq!(ch.members().iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), vec!["alice", "bob"]);
+    assert_eq!(ch.member(&"Alice".into()).map(|m| &m.privclass[..]), Some("Operators"));
+    assert_eq!(ch.member(&"bob".into()).map(|m| m.connections), Some(2));
+}
+
+#[test]
+fn channel_privclasses() {
+    let c = Channels::new();
+    c.observe(&Message::from(PRIVCLASSES));
+    let ch = c.get(&room()).unwrap();
+    assert_eq!(ch.privclasses()[0], (99, "Founders".to_string()));
+    assert_eq!(ch.privclass_order("Members"), Some(50));
+    assert_eq!(ch.privclass_order("Nobody"), None);
+}
+
+#[test]
+fn channel_topic_and_title() {
+    let c = Channels::new();
+    c.observe(&Message::from("property chat:room\np=topic\nby=alice\nts=1\n\nhello &amp; welcome\0"));
+    c.observe(&Message::from("property chat:room\np=title\nby=alice\nts=1\n\nThe Room\0"));
+    let ch = c.get(&room()).unwrap();
+    assert_eq!(ch.topic, "hello & welcome");
+    assert_eq!(ch.title, "The Room");
+}
+
+#[test]
+fn channel_recv_updates() {
+    let c = Channels::new();
+    c.observe(&Message::from(MEMBERS));
+    c.observe(&Message::from("recv chat:room\n\njoin carol\ns=0\n\npc=Guests\nusericon=0\nsymbol=~\nrealname=\ntypename=x\ngpc=guest\n\0"));
+    c.observe(&Message::from("recv chat:room\n\npart bob\n\0"));
+    c.observe(&Message::from("recv chat:room\n\nprivchg carol\nby=alice\npc=Members\n\0"));
+    c.observe(&Message::from("recv chat:room\n\nkicked alice\nby=alice\n\noops\0"));
+    let ch = c.get(&room()).unwrap();
+    assert_eq!(ch.member(&"bob".into()).map(|m| m.connections), Some(1));
+    assert_eq!(ch.member(&"carol".into()).map(|m| &m.privclass[..]), Some("Members"));
+    assert!(ch.member(&"alice".into()).is_none());
+
+    c.observe(&Message::from("recv chat:room\n\npart bob\n\0"));
+    assert!(c.get(&room()).unwrap().member(&"bob".into()).is_none());
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime,Local};
+use chrono::Duration as ChronoDuration;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration,Instant};
+
+/// Source of the current time for the message queue and everything that
+/// schedules through it.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn local(&self) -> DateTime<Local>;
+}
+
+pub type SharedClock = Rc<Clock>;
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn local(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// A clock that only moves when told to. Both readings advance together.
+pub struct ManualClock {
+    instant: Cell<Instant>,
+    local: Cell<DateTime<Local>>,
+}
+
+impl ManualClock {
+    pub fn new(local: DateTime<Local>) -> Self {
+        ManualClock {
+            instant: Cell::new(Instant::now()),
+            local: Cell::new(local),
+        }
+    }
+
+    pub fn advance(&self, d: Duration) {
+        self.instant.set(self.instant.get() + d);
+        self.local.set(self.local.get() + ChronoDuration::from_std(d).expect("duration out of range"));
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.instant.get()
+    }
+
+    fn local(&self) -> DateTime<Local> {
+        self.local.get()
+    }
+}
+
+pub fn system() -> SharedClock {
+    Rc::new(SystemClock)
+}
+
+#[test]
+fn manual_clock_advances_both() {
+    use chrono::TimeZone;
+
+    let clock = ManualClock::new(Local.ymd(2017, 5, 1).and_hms(12, 0, 0));
+    let before = clock.now();
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(clock.now() - before, Duration::from_secs(90));
+    assert_eq!(clock.local(), Local.ymd(2017, 5, 1).and_hms(12, 1, 30));
+}
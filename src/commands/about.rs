@@ -1,10 +1,56 @@
 extern crate rustc_version_runtime;
 
 use commands::prelude::*;
+use metrics;
 
-pub fn about(e: &Event) -> Hooks {
+pub struct About;
+
+impl Plugin for About {
+    fn name(&self) -> &'static str {
+        "about"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("about", cmd!(about))
+                 .category("Info").summary("Says what I am."),
+             Hook::register("botkin", |_| box |e| {
+                 e.respond("lol");
+                 e.respond_in("huh?", Duration::from_millis(500))?;
+                 Ok(vec![])
+             }).category("Fun"),
+             Hook::register("trigcheck", |_| box |e| {
+                 e.respond_highlight("!");
+                 Ok(vec![])
+             }).category("Info").summary("Checks that I'm listening."),
+             Hook::register("metrics", cmd!(metrics)).needs(Level::Owner)
+                 .category("Debug").summary("Counts how each command has turned out.")
+                 .args("[<command>]"),
+             Hook::register("panic", cmd!(die)).needs(Level::Owner)
+                 .category("Debug").summary("Panics, to check that I survive it.")]
+    }
+}
+
+pub fn about(e: &Event) -> Outcome {
     e.respond(format!("\u{1f370} <b>marsipan v{}</b> built with rustc-{}",
         env!("CARGO_PKG_VERSION"),
         rustc_version_runtime::version()));
-    vec![]
+    Ok(vec![])
+}
+
+fn metrics(e: &Event) -> Outcome {
+    let args = e.args();
+    let only = args.str("command").map(|c| c.trim_left_matches('!'));
+    let lines = metrics::snapshot().into_iter()
+        .filter(|&(ref hook, _, _)| only.map(|c| c == hook.as_str()).unwrap_or(true))
+        .map(|(hook, outcome, n)| format!("{} {}: {}", hook, outcome, n))
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        return Err(CommandError::NotFound("Nothing's been counted yet.".to_string()))
+    }
+    e.respond(lines.join("<br>"));
+    Ok(vec![])
+}
+
+fn die(_: &Event) -> Outcome {
+    panic!("!panic was used")
 }
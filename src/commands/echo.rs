@@ -1,10 +1,21 @@
 use commands::prelude::*;
 
-pub fn echo(e: &Event) -> Hooks {
-    if e.sender == b"participle" || e.message.len() <= 6 {
-        return vec![]
+pub struct Echo;
+
+impl Plugin for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
     }
 
-    e.respond(&e.message[6..]);
-    vec![]
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("echo", cmd!(echo))
+                 .category("Fun").summary("Repeats what you say.").args("<text...>")]
+    }
+}
+
+pub fn echo(e: &Event) -> Outcome {
+    if let Some(text) = e.args().str("text") {
+        e.respond(text);
+    }
+    Ok(vec![])
 }
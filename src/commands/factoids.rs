@@ -0,0 +1,323 @@
+use chrono::Timelike;
+use commands::prelude::*;
+use config;
+use diesel;
+use diesel::LoadDsl;
+use diesel::associations::HasTable;
+use hooks::Spec;
+use permissions::room_key;
+use script::models::Script;
+
+/// Answers `!name` with whatever was learned for it in the room. Every
+/// factoid name routes to one command, so names can come and go without
+/// a restart.
+pub struct Factoids {
+    /// Every name learned in any room, as of the last load.
+    known: Vec<String>,
+}
+
+impl Factoids {
+    pub fn new() -> Self {
+        Factoids { known: vec![] }
+    }
+}
+
+impl Plugin for Factoids {
+    fn name(&self) -> &'static str {
+        "factoids"
+    }
+
+    fn init(&mut self, ctx: &Context) {
+        use ::db::factoids::dsl::*;
+        use self::models::Factoid;
+
+        match factoids.load::<Factoid>(ctx.db) {
+            Ok(rows) => {
+                let mut names = rows.into_iter().map(|f| f.name).collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                self.known = names;
+            },
+            Err(err) => warn!("can't load factoids, so none will answer yet: {}", err),
+        }
+    }
+
+    fn commands(&self) -> Hooks {
+        let m = M::next();
+        let mut hooks = vec![
+            Hook::AddCommand(m, Spec::new("factoids"), box answer)
+                .category("Factoids").summary("Lists what I've learned here."),
+            Hook::register("learn", |_| box move |e| learn(e, m)).needs(Level::Admin)
+                .category("Factoids").summary("Teaches me something to say for !name; teach it again for variants.")
+                .args("<name> <text...>")
+                .examples(&["!learn hi Hello, $sender!", "!learn hug *hugs $args*"]),
+            Hook::register("forget", cmd!(forget)).needs(Level::Admin)
+                .category("Factoids").summary("Forgets a factoid, or one of its variants.")
+                .args("<name> [<variant:int>]"),
+            Hook::register("info", cmd!(info))
+                .category("Factoids").summary("Shows what a factoid says, and who taught it.")
+                .args("<name>")];
+        hooks.extend(self.known.iter().map(|n| Hook::AddRoute(m, n.clone())));
+        hooks
+    }
+}
+
+mod models {
+    use ::db::factoids;
+
+    #[derive(Queryable,Debug)]
+    pub struct Factoid {
+        pub id: i32,
+        pub channel: String,
+        pub name: String,
+        pub body: String,
+        pub author: String,
+    }
+
+    #[derive(Insertable,Debug)]
+    #[table_name="factoids"]
+    pub struct NewFactoid {
+        pub channel: String,
+        pub name: String,
+        pub body: String,
+        pub author: String,
+    }
+
+    impl Factoid {
+        /// The factoid's variants in every room.
+        pub fn named(n: &str) -> ::diesel::helper_types::FindBy<
+                ::db::factoids::dsl::factoids,
+                ::db::factoids::dsl::name,
+                String> {
+            use ::db::factoids::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            factoids.filter(name.eq(n.to_lowercase()))
+        }
+
+        pub fn in_room(room: &str) -> ::diesel::helper_types::FindBy<
+                ::db::factoids::dsl::factoids,
+                ::db::factoids::dsl::channel,
+                String> {
+            use ::db::factoids::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            factoids.filter(channel.eq(room.to_string()))
+        }
+    }
+}
+
+use self::models::{Factoid,NewFactoid};
+
+/// The factoid's variants in the event's room, oldest first.
+fn variants(e: &Event, n: &str) -> Result<Vec<Factoid>, CommandError> {
+    let room = room_key(&e.chatroom);
+    let mut found = e.load(Factoid::named(n))?.into_iter()
+        .filter(|f: &Factoid| f.channel == room)
+        .collect::<Vec<_>>();
+    found.sort_by_key(|f| f.id);
+    Ok(found)
+}
+
+/// Like `variants`, but there has to be at least one.
+fn known(e: &Event, n: &str) -> Result<Vec<Factoid>, CommandError> {
+    let found = variants(e, n)?;
+    if found.is_empty() {
+        return Err(CommandError::NotFound(format!("I don't know !{} here.", n)))
+    }
+    Ok(found)
+}
+
+/// Fills in `$sender`, `$args` and `$channel`, in one pass, so what's
+/// filled in is never expanded again.
+fn expand(body: &str, sender: &str, args: &str, channel: &str) -> String {
+    let vars = [("$sender", sender), ("$args", args), ("$channel", channel)];
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match vars.iter().find(|&&(var, _)| rest.starts_with(var)) {
+            Some(&(var, value)) => {
+                out.push_str(value);
+                rest = &rest[var.len()..];
+            },
+            None => {
+                out.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn pick<T>(items: &[T], seed: usize) -> Option<&T> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(&items[seed % items.len()])
+    }
+}
+
+fn answer(e: &Event) -> Outcome {
+    let n = match e.invocation() {
+        Some((cmd, _)) => cmd.to_lowercase(),
+        None => return Ok(vec![])
+    };
+    if n == "factoids" {
+        let mut names = e.load(Factoid::in_room(&room_key(&e.chatroom)))?.into_iter()
+            .map(|f: Factoid| f.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            e.respond_highlight("I haven't learned anything here yet.");
+        } else {
+            e.respond_highlight(format!("I know: {}", names.join(", ")));
+        }
+        return Ok(vec![])
+    }
+    if config::is_disabled(e, &n)? {
+        return Ok(vec![])
+    }
+
+    let found = variants(e, &n)?;
+    if let Some(f) = pick(&found, e.local_now().nanosecond() as usize) {
+        e.respond(expand(&f.body, &e.sender.to_string(), e.content(), &e.chatroom.shorthand()));
+    }
+    Ok(vec![])
+}
+
+fn learn(e: &Event, m: M) -> Outcome {
+    use ::db::factoids::dsl::*;
+
+    let args = e.args();
+    let n = args.str("name").unwrap_or("").trim_left_matches('!').to_lowercase();
+    let text = args.str("text").unwrap_or("");
+    if n.is_empty() || !n.chars().all(|c: char| c.is_alphanumeric() || c == '_' || c == '-') {
+        return Err(CommandError::Usage("Factoid names are letters, numbers, - and _.".to_string()))
+    }
+    if e.commands().find(&n).is_some() {
+        return Err(CommandError::Denied(format!("!{} is already a command.", n)))
+    }
+    // Factoid names route in every room, so a script anywhere would be hidden.
+    if !e.load::<_, Script>(Script::named(&n))?.is_empty() {
+        return Err(CommandError::Denied(format!("!{} is already a script.", n)))
+    }
+    if variants(e, &n)?.iter().any(|f| f.body == text) {
+        e.respond_highlight(format!("I already say that for !{}.", n));
+        return Ok(vec![])
+    }
+
+    let first = e.load::<_, Factoid>(Factoid::named(&n))?.is_empty();
+    e.execute(diesel::insert(&NewFactoid {
+        channel: room_key(&e.chatroom),
+        name: n.clone(),
+        body: text.to_string(),
+        author: e.sender.to_string(),
+    }).into(factoids::table()))?;
+    e.respond_highlight(format!("Learned !{}.", n));
+
+    // The route is shared by every room, so only the first one adds it.
+    if first {
+        Ok(vec![Hook::AddRoute(m, n)])
+    } else {
+        Ok(vec![])
+    }
+}
+
+fn forget(e: &Event) -> Outcome {
+    use ::db::factoids::dsl::*;
+    use diesel::{ExpressionMethods,FilterDsl};
+
+    let args = e.args();
+    let n = args.str("name").unwrap_or("").trim_left_matches('!').to_lowercase();
+    let found = known(e, &n)?;
+
+    match args.int("variant") {
+        Some(v) if v < 1 || v as usize > found.len() => {
+            return Err(CommandError::NotFound(format!("!{} only has {} variants.", n, found.len())))
+        },
+        Some(v) => {
+            e.execute(diesel::delete(factoids.filter(id.eq(found[v as usize - 1].id))))?;
+            e.respond_highlight(format!("Forgot variant {} of !{}.", v, n));
+        },
+        None => {
+            e.execute(diesel::delete(Factoid::in_room(&room_key(&e.chatroom)).filter(name.eq(n.clone()))))?;
+            e.respond_highlight(format!("Forgot !{}.", n));
+        }
+    }
+
+    if e.load::<_, Factoid>(Factoid::named(&n))?.is_empty() {
+        Ok(vec![Hook::DropRoute(n)])
+    } else {
+        Ok(vec![])
+    }
+}
+
+fn info(e: &Event) -> Outcome {
+    let n = e.args().str("name").unwrap_or("").trim_left_matches('!').to_lowercase();
+    let lines = known(e, &n)?.iter().enumerate()
+        .map(|(i, f)| format!("{}. <code>{}</code> (by {})", i + 1, f.body, f.author))
+        .collect::<Vec<_>>();
+    e.respond(format!("<b>!{}</b><br>{}", n, lines.join("<br>")));
+    Ok(vec![])
+}
+
+#[test]
+fn factoids_expand() {
+    assert_eq!(expand("Hi $sender, welcome to $channel!", "bob", "", "#room"),
+               "Hi bob, welcome to #room!");
+    assert_eq!(expand("*hugs $args*", "bob", "alice and carol", "#room"),
+               "*hugs alice and carol*");
+    assert_eq!(expand("no vars", "bob", "x", "#room"), "no vars");
+    assert_eq!(expand("$args, $sender", "bob", "$channel $sender", "#room"), "$channel $sender, bob");
+    assert_eq!(expand("$5 or $$", "bob", "", "#room"), "$5 or $$");
+}
+
+#[test]
+fn factoids_pick() {
+    let v = vec!["a", "b", "c"];
+    assert_eq!(pick(&v, 0), Some(&"a"));
+    assert_eq!(pick(&v, 4), Some(&"b"));
+    assert_eq!(pick::<&str>(&[], 4), None);
+}
+
+#[test]
+fn factoids_through_dispatch() {
+    use hooks::HookStorage;
+    use permissions::{ADMIN,user_key};
+    use permissions::models::NewGrant;
+
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_msg(&mq, "hi");
+    e.execute(diesel::insert(&NewGrant { user: user_key(&e.sender), channel: room_key(&e.chatroom), name: ADMIN.to_string() })
+        .into(::db::grants::table)).unwrap();
+    // Everything alice says goes to the same database.
+    let say = |text: &str| {
+        let mut ev = e.clone();
+        ev.message = text.to_string();
+        ev
+    };
+    let mut hooks = HookStorage::new();
+    hooks.apply(Factoids::new().commands());
+
+    hooks.dispatch(&say("!learn greet Hello, $sender!"));
+    assert!(hooks.commands().routes("greet"));
+    hooks.dispatch(&say("!greet"));
+    hooks.dispatch(&say("!forget greet"));
+    assert!(!hooks.commands().routes("greet"));
+    hooks.dispatch(&say("!greet"));
+
+    let said = mq.jobs().into_iter()
+        .map(|(_, _, job)| String::from_utf8_lossy(&job.message.as_bytes()).into_owned())
+        .collect::<Vec<_>>();
+    assert!(said.iter().any(|s| s.contains("alice: Learned !greet.")));
+    assert!(said.iter().any(|s| s.contains("alice: Forgot !greet.")));
+    assert_eq!(said.iter().filter(|s| s.contains("Hello, alice!")).count(), 1);
+}
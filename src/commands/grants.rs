@@ -0,0 +1,123 @@
+use commands::prelude::*;
+use damnpacket::Username;
+use diesel;
+use diesel::associations::HasTable;
+use permissions::{ADMIN,EVERYWHERE,room_key,user_key};
+use permissions::models::{Grant,NewGrant};
+
+pub struct Grants;
+
+impl Plugin for Grants {
+    fn name(&self) -> &'static str {
+        "grants"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("grant", cmd!(grant)).needs(Level::Admin)
+                 .category("Admin")
+                 .summary("Lets someone use the command <i>what</i> here, or makes them an admin if <i>what</i> is admin.")
+                 .args("<user:user> <what> [everywhere]")
+                 .examples(&["!grant alice kick", "!grant bob admin everywhere"]),
+             Hook::register("perms", cmd!(perms))
+                 .category("Info").summary("Shows what someone has been granted here.")
+                 .args("[<user:user>]"),
+             Hook::register("revoke", cmd!(revoke)).needs(Level::Admin)
+                 .category("Admin").summary("Takes back a grant.")
+                 .args("<user:user> <what> [everywhere]")]
+    }
+}
+
+/// Whether someone may hand out `name`, which asks for `level`. A grant lets
+/// someone past the command's level, so admins may only hand out commands
+/// they could run themselves, and only in their own room; only the owner
+/// makes admins, grants things everywhere, or grants owner commands.
+fn may_grant(name: &str, level: Level, everywhere: bool, owner: bool) -> Result<(), CommandError> {
+    if !owner && (name == ADMIN || everywhere || level == Level::Owner) {
+        return Err(CommandError::Denied("Only my owner can do that.".to_string()))
+    }
+    Ok(())
+}
+
+/// Works out the user, grant and channel from `<user> <what> [everywhere]`.
+fn parse(e: &Event) -> Result<(Username, String, String), CommandError> {
+    let args = e.args();
+    let what = args.str("what").unwrap_or("");
+    let (name, level) = if what == ADMIN {
+        (ADMIN, Level::Admin)
+    } else {
+        match e.commands().find(what) {
+            Some(s) => (s.name, s.level),
+            None => return Err(CommandError::NotFound(format!("There's no {} command.", what))),
+        }
+    };
+    may_grant(name, level, args.has("everywhere"), e.is_owner())?;
+
+    let channel = if args.has("everywhere") {
+        EVERYWHERE.to_string()
+    } else {
+        room_key(&e.chatroom)
+    };
+
+    args.user("user").map(|u| (u.clone(), name.to_string(), channel))
+        .ok_or_else(|| CommandError::Usage("Who, though?".to_string()))
+}
+
+pub fn grant(e: &Event) -> Outcome {
+    use ::db::grants::dsl::*;
+
+    let (u, n, c) = parse(e)?;
+    e.execute(diesel::insert_or_replace(&NewGrant {
+        user: user_key(&u),
+        channel: c,
+        name: n.clone(),
+    }).into(grants::table()))?;
+    e.respond_highlight(format!("{} now has {}.", u, n));
+    Ok(vec![])
+}
+
+pub fn revoke(e: &Event) -> Outcome {
+    use ::db::grants::dsl::*;
+    use diesel::{ExpressionMethods,FilterDsl};
+
+    let (u, n, c) = parse(e)?;
+    let affected = e.execute(diesel::delete(Grant::of(&user_key(&u))
+        .filter(channel.eq(c))
+        .filter(name.eq(n.clone()))))?;
+    if affected == 0 {
+        return Err(CommandError::NotFound(format!("{} didn't have {}.", u, n)))
+    }
+    e.respond_highlight(format!("{} no longer has {}.", u, n));
+    Ok(vec![])
+}
+
+pub fn perms(e: &Event) -> Outcome {
+    let u = e.args().user("user").cloned().unwrap_or_else(|| e.sender.clone());
+
+    let here = room_key(&e.chatroom);
+    let mut found = e.load(Grant::of(&user_key(&u)))?.into_iter()
+        .filter(|g: &Grant| g.channel == here || g.channel == EVERYWHERE)
+        .map(|g| if g.channel == EVERYWHERE {
+            format!("{} (everywhere)", g.name)
+        } else {
+            g.name
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+
+    if found.is_empty() {
+        e.respond_highlight(format!("{} has no grants here.", u));
+    } else {
+        e.respond_highlight(format!("{} has: {}", u, found.join(", ")));
+    }
+    Ok(vec![])
+}
+
+#[test]
+fn grants_stay_below_the_granter() {
+    assert!(may_grant("kick", Level::Admin, false, false).is_ok());
+    assert!(may_grant("ping", Level::Anyone, false, false).is_ok());
+    assert!(may_grant("load", Level::Owner, false, false).is_err());
+    assert!(may_grant(ADMIN, Level::Admin, false, false).is_err());
+    assert!(may_grant("kick", Level::Admin, true, false).is_err());
+    assert!(may_grant("load", Level::Owner, true, true).is_ok());
+}
@@ -0,0 +1,124 @@
+use commands::prelude::*;
+use hooks::Spec;
+use std::collections::BTreeMap;
+
+pub struct Help;
+
+impl Plugin for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("commands", cmd!(commands))
+                 .category("Info").summary("Lists everything I can do."),
+             Hook::register("help", cmd!(help))
+                 .category("Info").summary("Explains a command.").args("<command>")
+                 .examples(&["!help ww"])]
+    }
+}
+
+fn render(s: &Spec) -> String {
+    let mut out = format!("<b>!{}</b>", s.name);
+    let usage = s.usage();
+    if !usage.is_empty() {
+        out.push_str(&format!(" {}", usage));
+    }
+    if !s.summary.is_empty() {
+        out.push_str(&format!(": {}", s.summary));
+    }
+    if !s.aliases.is_empty() {
+        let aliases = s.aliases.iter().map(|a| format!("!{}", a)).collect::<Vec<_>>();
+        out.push_str(&format!("<br>Also answers to {}.", aliases.join(", ")));
+    }
+    if !s.examples.is_empty() {
+        let examples = s.examples.iter().map(|x| format!("<code>{}</code>", x)).collect::<Vec<_>>();
+        out.push_str(&format!("<br>Examples: {}", examples.join(", ")));
+    }
+    match s.scope {
+        Scope::Public => out.push_str("<br>Only works in chatrooms."),
+        Scope::Private => out.push_str("<br>Only works in private chats."),
+        Scope::Anywhere => {}
+    }
+    if s.level != Level::Anyone {
+        out.push_str(&format!("<br>Only {} can use it.", s.level));
+    }
+    out
+}
+
+/// Names sorted within their categories, categories in alphabetical order.
+fn listing(specs: &[Spec]) -> String {
+    let mut groups = BTreeMap::new();
+    for s in specs.iter() {
+        groups.entry(s.category).or_insert(vec![]).push(s.name);
+    }
+    groups.into_iter().map(|(category, mut names)| {
+        names.sort();
+        format!("<b>{}</b>: {}", category, names.join(", "))
+    }).collect::<Vec<_>>().join("<br>")
+}
+
+pub fn help(e: &Event) -> Outcome {
+    let args = e.args();
+    let cmd = args.str("command").unwrap_or("");
+    let s = e.commands().find(cmd.trim_left_matches('!')).ok_or_else(||
+        CommandError::NotFound(format!("There's no {} command; try !commands.", cmd)))?;
+    e.respond(render(&s));
+    Ok(vec![])
+}
+
+pub fn commands(e: &Event) -> Outcome {
+    e.respond(format!("{}<br>Try !help <i>command</i> for more.", listing(&e.commands().all())));
+    Ok(vec![])
+}
+
+#[cfg(test)]
+fn spec(name: &'static str, category: &'static str) -> Spec {
+    let mut s = Spec::new(name);
+    s.category = category;
+    s
+}
+
+#[test]
+fn help_render() {
+    let mut s = spec("kick", "Moderation");
+    s.summary = "Kicks someone out of the room.";
+    s.args = Some("<user:user> [<reason...>]");
+    s.examples = &["!kick spammer bye"];
+    s.aliases = &["boot"];
+    s.scope = Scope::Public;
+    s.level = Level::Admin;
+    assert_eq!(render(&s),
+        "<b>!kick</b> <i>user</i> [<i>reason</i>...]: Kicks someone out of the room.\
+         <br>Also answers to !boot.\
+         <br>Examples: <code>!kick spammer bye</code>\
+         <br>Only works in chatrooms.\
+         <br>Only admins of this room can use it.");
+    assert_eq!(render(&spec("ping", "Info")), "<b>!ping</b>");
+}
+
+#[test]
+fn help_listing() {
+    let specs = vec![spec("ping", "Info"), spec("ban", "Moderation"),
+                     spec("about", "Info"), spec("kick", "Moderation")];
+    assert_eq!(listing(&specs),
+        "<b>Info</b>: about, ping<br><b>Moderation</b>: ban, kick");
+}
+
+#[test]
+fn help_indexes_what_was_routed() {
+    use hooks::HookStorage;
+
+    let first = Hook::register("helptest", cmd!(help)).summary("first");
+    let m = match first {
+        Hook::AddCommand(m, _, _) => m,
+        _ => unreachable!(),
+    };
+    let mut hooks = HookStorage::new();
+    hooks.apply(vec![first, Hook::register("helptest", cmd!(help)).summary("second")]);
+    assert_eq!(hooks.commands().find("helptest").map(|s| s.summary), Some("first"));
+
+    hooks.apply(vec![Hook::unregister(m)]);
+    assert!(hooks.commands().find("helptest").is_none());
+    assert!(!hooks.commands().routes("helptest"));
+}
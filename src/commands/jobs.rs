@@ -0,0 +1,115 @@
+use args::MAX_DURATION;
+use commands::prelude::*;
+use messagequeue::JobId;
+
+pub struct Jobs;
+
+impl Plugin for Jobs {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("jobs", cmd!(jobs)).needs(Level::Owner)
+                 .category("Admin").summary("Shows and manages scheduled messages.")
+                 .args("[list] | cancel <id> | clear here | clear user <name:user> | \
+                        clear channel <room:room> | every <minutes:int> <message...>")
+                 .examples(&["!jobs cancel 3", "!jobs every 60 Drink some water!"])]
+    }
+}
+
+fn human(d: Duration) -> String {
+    let s = d.as_secs();
+    match (s / 3600, (s / 60) % 60, s % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+pub fn jobs(e: &Event) -> Outcome {
+    let args = e.args();
+    match args.sub() {
+        "cancel" => jobs_cancel(e, args.str("id").unwrap_or(""))?,
+        "clear here" => jobs_cleared(e, e.cancel_jobs_in(&e.chatroom)),
+        "clear user" => if let Some(u) = args.user("name") {
+            jobs_cleared(e, e.cancel_jobs_of(u))
+        },
+        "clear channel" => if let Some(r) = args.room("room") {
+            jobs_cleared(e, e.cancel_jobs_in(r))
+        },
+        "every" => jobs_every(e, args.int("minutes").unwrap_or(0), args.str("message").unwrap_or(""))?,
+        _ => jobs_list(e),
+    };
+
+    Ok(vec![])
+}
+
+fn jobs_list(e: &Event) {
+    let now = e.now();
+    let mut response = "<ul>".to_string();
+    let mut any = false;
+    for (id, at, job) in e.jobs().into_iter() {
+        if at <= now && job.every.is_none() {
+            continue
+        }
+        any = true;
+        // A repeating job can be due but not yet sent and rescheduled.
+        let due = if at > now { at - now } else { Duration::new(0, 0) };
+
+        let mut summary = job.description.chars().take(50).collect::<String>();
+        if summary.len() < job.description.len() {
+            summary.push_str("...");
+        }
+        response.push_str(&format!(
+            "<li>#{id} in {due}{every} (<b>{owner}</b>, {channel}): {summary}</li>",
+            id = id,
+            due = human(due),
+            every = job.every.map(|d| format!(", every {}", human(d))).unwrap_or(String::new()),
+            owner = job.owner.map(|o| o.to_string()).unwrap_or("?".to_string()),
+            channel = job.channel.map(|c| c.shorthand()).unwrap_or("?".to_string()),
+            summary = summary));
+    }
+    response.push_str("</ul>");
+
+    if any {
+        e.respond(response);
+    } else {
+        e.respond_highlight("No pending jobs.");
+    }
+}
+
+fn jobs_cancel(e: &Event, id: &str) -> Result<(), CommandError> {
+    let id = id.trim_left_matches('#').parse::<JobId>()
+        .map_err(|_| CommandError::Usage("That doesn't look like a job ID.".to_string()))?;
+    match e.cancel_job(id) {
+        Some(_) => {
+            e.respond_highlight(format!("Cancelled job #{}.", id));
+            Ok(())
+        },
+        None => Err(CommandError::NotFound("No job with that ID found.".to_string())),
+    }
+}
+
+fn jobs_cleared(e: &Event, n: usize) {
+    e.respond_highlight(format!("Cancelled {} job(s).", n));
+}
+
+fn jobs_every(e: &Event, minutes: i64, msg: &str) -> Result<(), CommandError> {
+    if minutes <= 0 {
+        return Err(CommandError::Usage("That doesn't look like a number of minutes.".to_string()))
+    }
+    if minutes as u64 > MAX_DURATION / 60 {
+        return Err(CommandError::Usage(format!("That's too long; jobs can repeat every {} minutes at most.", MAX_DURATION / 60)))
+    }
+    let id = e.respond_every(msg, Duration::from_secs(minutes as u64 * 60))?;
+    e.respond_highlight(format!("Scheduled job #{}.", id));
+    Ok(())
+}
+
+#[test]
+fn human_durations() {
+    assert_eq!(human(Duration::from_secs(42)), "42s");
+    assert_eq!(human(Duration::from_secs(125)), "2m5s");
+    assert_eq!(human(Duration::from_secs(3 * 3600 + 61)), "3h1m");
+}
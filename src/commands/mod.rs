@@ -1,59 +1,66 @@
-use hooks::{Command,Hook,Hooks};
-use std::time::Duration;
+use diesel::sqlite::SqliteConnection;
+use hooks::Hooks;
+use plugin::{Plugin,Registry};
+
+macro_rules! cmd {
+    ($e:expr) => { |_| box $e as ::hooks::Command };
+}
 
 mod about;
-mod commands;
 mod echo;
+mod factoids;
+mod grants;
+mod help;
+mod jobs;
+mod moderation;
+mod mute;
 mod ping;
+mod plugins;
 mod prelude;
+mod scripts;
+mod settings;
 mod sleep;
 mod welcome;
 mod wordwar;
 
-macro_rules! cmd {
-    ($e:expr) => { |_| box $e as Command };
+/// Every plugin `!load` and `!unload` know about, in the order their hooks
+/// are added.
+pub fn plugins() -> Vec<Box<Plugin>> {
+    vec![box help::Help,
+         box grants::Grants,
+         box settings::Settings,
+         box about::About,
+         box echo::Echo,
+         box factoids::Factoids::new(),
+         box jobs::Jobs,
+         box moderation::Moderation,
+         box mute::Muting,
+         box ping::Ping,
+         box scripts::Scripts,
+         box sleep::Sleep::new(),
+         box welcome::Welcomes,
+         box wordwar::WordWar::new()]
 }
 
-macro_rules! cmds {
-    (
-        cmd => [$($e:expr => $f:expr),*],
-        msg => [$($g:expr),*],
-        join => [$($h:expr),*]
-    ) => {
-        static CMD_NAMES: &'static [&'static str] = &[$($e,)*];
-
-        let mut v = vec![$(Hook::register($e, $f),)*];
-        v.extend(vec![$(Hook::register_msg($g),)*]);
-        v.extend(vec![$(Hook::join($h),)*]);
-        v
-    }
+pub fn default_cmds(db: &SqliteConnection) -> Hooks {
+    let registry = Registry::new(plugins());
+    let mut hooks = registry.load_all(db);
+    hooks.extend(plugins::Plugins::new(registry).commands());
+    hooks
 }
 
-pub fn default_cmds() -> Hooks {
-    wordwar::wars_init();
-
-    cmds! {
-        cmd => [ "about" => cmd!(about::about),
-                 "commands" => |_| commands::commands(CMD_NAMES),
-                 "echo" => cmd!(echo::echo),
-                 "ping" => cmd!(ping::ping),
-                 "sleep" => cmd!(sleep::sleep),
-                 "wakeup" => cmd!(sleep::wakeup),
-                 "welcome" => cmd!(welcome::welcome),
-                 "ww" => cmd!(wordwar::wordwar),
-
-                 "botkin" => |_| box |e| {
-                     e.respond("lol");
-                     e.respond_in("huh?", Duration::from_millis(500));
-                     vec![]
-                 },
-                 "trigcheck" => |_| box |e| {
-                     e.respond_highlight("!");
-                     vec![]
-                 } ],
-
-        msg => [],
+#[test]
+fn commands_declare_good_arguments() {
+    use args::Syntax;
+    use hooks::Hook;
 
-        join => [ cmd!(welcome::say_welcome) ]
+    let mut hooks = plugins().into_iter().flat_map(|p| p.commands()).collect::<Vec<_>>();
+    hooks.extend(plugins::Plugins::new(Registry::new(plugins())).commands());
+    for h in hooks.iter() {
+        if let Hook::AddCommand(_, ref s, _) = *h {
+            if let Some(decl) = s.args {
+                assert!(Syntax::parse(decl).is_ok(), "!{} declares bad arguments: {}", s.name, decl);
+            }
+        }
     }
 }
@@ -0,0 +1,88 @@
+use commands::prelude::*;
+use permissions;
+
+pub struct Moderation;
+
+impl Plugin for Moderation {
+    fn name(&self) -> &'static str {
+        "moderation"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("ban", cmd!(ban)).only(Scope::Public).needs(Level::Admin)
+                 .category("Moderation").summary("Bans someone from the room.")
+                 .args("<user:user>"),
+             Hook::register("demote", cmd!(demote)).only(Scope::Public).needs(Level::Admin)
+                 .category("Moderation").summary("Moves someone to a lower privclass.")
+                 .args("<user:user> [<privclass...>]"),
+             Hook::register("kick", cmd!(kick)).only(Scope::Public).needs(Level::Admin)
+                 .category("Moderation").summary("Kicks someone out of the room.")
+                 .args("<user:user> [<reason...>]").examples(&["!kick spammer Enough."]),
+             Hook::register("promote", cmd!(promote)).only(Scope::Public).needs(Level::Admin)
+                 .category("Moderation").summary("Moves someone to a higher privclass.")
+                 .args("<user:user> [<privclass...>]"),
+             Hook::register("topic", cmd!(topic)).only(Scope::Public)
+                 .category("Moderation").summary("Shows the topic, or changes it if you're an admin.")
+                 .args("[<topic...>]"),
+             Hook::register("unban", cmd!(unban)).only(Scope::Public).needs(Level::Admin)
+                 .category("Moderation").summary("Lets a banned user back in.")
+                 .args("<user:user>")]
+    }
+}
+
+pub fn kick(e: &Event) -> Outcome {
+    let args = e.args();
+    if let Some(u) = args.user("user") {
+        e.kick(u, args.str("reason"));
+    }
+    Ok(vec![])
+}
+
+pub fn ban(e: &Event) -> Outcome {
+    if let Some(u) = e.args().user("user") {
+        e.ban(u);
+    }
+    Ok(vec![])
+}
+
+pub fn unban(e: &Event) -> Outcome {
+    if let Some(u) = e.args().user("user") {
+        e.unban(u);
+    }
+    Ok(vec![])
+}
+
+pub fn promote(e: &Event) -> Outcome {
+    let args = e.args();
+    if let Some(u) = args.user("user") {
+        e.promote(u, args.str("privclass"));
+    }
+    Ok(vec![])
+}
+
+pub fn demote(e: &Event) -> Outcome {
+    let args = e.args();
+    if let Some(u) = args.user("user") {
+        e.demote(u, args.str("privclass"));
+    }
+    Ok(vec![])
+}
+
+pub fn topic(e: &Event) -> Outcome {
+    match e.args().str("topic") {
+        None => {
+            let topic = e.channel().topic;
+            if topic.is_empty() {
+                e.respond_highlight("There's no topic.");
+            } else {
+                e.respond_highlight(format!("The topic is: {}", topic));
+            }
+        },
+        Some(t) => if permissions::allowed(e, Level::Admin, "topic")? {
+            e.set_topic(t);
+        } else {
+            return Err(CommandError::Denied(format!("Only {} can change the topic.", Level::Admin)))
+        }
+    }
+    Ok(vec![])
+}
@@ -0,0 +1,53 @@
+use commands::prelude::*;
+use damnpacket::ChannelName;
+use outgoing::{DryRun,Mute};
+
+pub struct Muting;
+
+impl Plugin for Muting {
+    fn name(&self) -> &'static str {
+        "mute"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("dryrun", cmd!(dryrun)).needs(Level::Owner)
+                 .category("Admin").summary("Stops me sending anything, without forgetting it.")
+                 .args("on | off"),
+             Hook::register("mute", cmd!(mute)).needs(Level::Owner)
+                 .category("Admin").summary("Stops me talking in a room.")
+                 .args("[<room:room>]"),
+             Hook::register("unmute", cmd!(unmute)).needs(Level::Owner)
+                 .category("Admin").summary("Lets me talk in a room again.")
+                 .args("[<room:room>]")]
+    }
+}
+
+fn room(e: &Event) -> ChannelName {
+    e.args().room("room").cloned().unwrap_or_else(|| e.chatroom.clone())
+}
+
+pub fn mute(e: &Event) -> Outcome {
+    let r = room(e);
+    e.with_filter(|m: &mut Mute| m.mute(&r));
+    if r != e.chatroom {
+        e.respond_highlight(format!("Muted {}.", r.shorthand()));
+    }
+    Ok(vec![])
+}
+
+pub fn unmute(e: &Event) -> Outcome {
+    let r = room(e);
+    match e.with_filter(|m: &mut Mute| m.unmute(&r)) {
+        Some(true) => e.respond_highlight(format!("Unmuted {}.", r.shorthand())),
+        _ => e.respond_highlight(format!("{} wasn't muted.", r.shorthand())),
+    };
+    Ok(vec![])
+}
+
+pub fn dryrun(e: &Event) -> Outcome {
+    let enable = e.args().sub() == "on";
+    e.with_filter(|d: &mut DryRun| d.0 = enable);
+    // Filters run when a message leaves the queue, so "on" only shows up in the log.
+    e.respond_highlight(format!("Dry run is {}.", e.args().sub()));
+    Ok(vec![])
+}
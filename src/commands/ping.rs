@@ -1,18 +1,44 @@
 use commands::prelude::*;
-use std::time::Instant;
 
-pub fn ping(e: &Event) -> Hooks {
+pub struct Ping;
+
+impl Plugin for Ping {
+    fn name(&self) -> &'static str {
+        "ping"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("ping", cmd!(ping))
+                 .category("Info").summary("Measures how long my messages take.")]
+    }
+}
+
+pub fn ping(e: &Event) -> Outcome {
     e.respond("\u{1f514}?");
-    let t = Instant::now();
-    vec![Hook::register_msg(|m| box move |e| {
-        if e.message == "\u{1f514}?" {
-            let diff = Instant::now() - t;
-            let ms = (diff.subsec_nanos() as u64 / 1000000)
-                + diff.as_secs() * 1000;
-            e.respond(format!("\u{1f514}! ({}ms)", ms));
-            return vec![Hook::unregister(m)];
+    let t = e.now();
+    Ok(vec![Hook::register_msg(|_| box move |e| {
+        if e.message != "\u{1f514}?" {
+            return Ok(vec![Hook::Ignored]);
         }
 
-        vec![]
-    })]
+        e.respond(pong(e.now() - t));
+        Ok(vec![])
+    }).once().until(t + Duration::from_secs(60)).while_in(e.chatroom.clone())])
+}
+
+fn pong(diff: Duration) -> String {
+    let ms = (diff.subsec_nanos() as u64 / 1000000)
+        + diff.as_secs() * 1000;
+    format!("\u{1f514}! ({}ms)", ms)
+}
+
+#[test]
+fn pong_latency() {
+    use chrono::{Local,TimeZone};
+    use clock::{Clock,ManualClock};
+
+    let clock = ManualClock::new(Local.ymd(2017, 5, 1).and_hms(12, 0, 0));
+    let t = clock.now();
+    clock.advance(Duration::new(1, 234567890));
+    assert_eq!(pong(clock.now() - t), "\u{1f514}! (1234ms)");
 }
@@ -0,0 +1,63 @@
+use commands::prelude::*;
+use plugin::Registry;
+
+/// Loads and unloads the other plugins. It isn't in the registry itself,
+/// so it can't unload itself and leave nothing to load it back.
+pub struct Plugins {
+    registry: Registry,
+}
+
+impl Plugins {
+    pub fn new(registry: Registry) -> Self {
+        Plugins { registry: registry }
+    }
+}
+
+impl Plugin for Plugins {
+    fn name(&self) -> &'static str {
+        "plugins"
+    }
+
+    fn commands(&self) -> Hooks {
+        let (r1, r2, r3, r4) = (self.registry.clone(), self.registry.clone(),
+                                self.registry.clone(), self.registry.clone());
+        vec![Hook::register("plugins", |_| box move |e| plugins(e, &r1))
+                 .category("Admin").summary("Lists plugins, and whether they're loaded."),
+             Hook::register("load", |_| box move |e| {
+                 change(e, "Loaded", |name| r2.load(name, e.db()))
+             }).needs(Level::Owner)
+                 .category("Admin").summary("Turns a plugin's commands on.").args("<plugin>"),
+             Hook::register("unload", |_| box move |e| {
+                 change(e, "Unloaded", |name| r3.unload(name))
+             }).needs(Level::Owner)
+                 .category("Admin").summary("Turns a plugin's commands off, keeping what it remembers.")
+                 .args("<plugin>"),
+             Hook::register("reload", |_| box move |e| {
+                 change(e, "Reloaded", |name| r4.reload(name, e.db()))
+             }).needs(Level::Owner)
+                 .category("Admin").summary("Unloads a plugin and loads it again.").args("<plugin>")]
+    }
+}
+
+fn plugins(e: &Event, registry: &Registry) -> Outcome {
+    let (on, off): (Vec<_>, Vec<_>) = registry.status().into_iter().partition(|&(_, loaded)| loaded);
+    let names = |v: Vec<(&str, bool)>| v.into_iter().map(|(n, _)| n).collect::<Vec<_>>().join(", ");
+    if off.is_empty() {
+        e.respond_highlight(format!("Loaded: {}.", names(on)));
+    } else {
+        e.respond_highlight(format!("Loaded: {}. Unloaded: {}.", names(on), names(off)));
+    }
+    Ok(vec![])
+}
+
+/// Runs `f` on the named plugin.
+fn change<F>(e: &Event, done: &str, f: F) -> Outcome
+    where F: FnOnce(&str) -> Result<Hooks, CommandError> {
+    let name = e.args().str("plugin").unwrap_or("").to_lowercase();
+    if name == "plugins" {
+        return Err(CommandError::Denied("That one stays; it's how plugins get loaded.".to_string()))
+    }
+    let hooks = f(&name)?;
+    e.respond_highlight(format!("{} {}.", done, name));
+    Ok(hooks)
+}
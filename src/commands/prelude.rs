@@ -1,4 +1,6 @@
-pub use hooks::{Hook,Hooks,M,J,W,Command};
-pub use event::{Event,word};
+pub use hooks::{Hook,Hooks,M,J,P,V,W,Command,CommandError,Outcome,Priority,Scope};
+pub use event::{AdminOp,EType,Event,word};
+pub use permissions::Level;
+pub use plugin::{Context,Plugin};
 pub use std::time::{Duration,Instant};
 pub use diesel::helper_types::*;
@@ -0,0 +1,113 @@
+use commands::prelude::*;
+use config;
+use diesel;
+use diesel::associations::HasTable;
+use handler::NAME;
+use permissions::room_key;
+use script;
+use script::models::{Datum,NewScript,Script};
+
+pub struct Scripts;
+
+impl Plugin for Scripts {
+    fn name(&self) -> &'static str {
+        "scripts"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("script", cmd!(script)).only(Scope::Public).needs(Level::Admin)
+                 .category("Admin").summary("Makes commands for this room out of small Lua scripts.")
+                 .args("add <name> <code...> | remove <name> | show <name> | [list]")
+                 .examples(&["!script add hi respond(\"Hi, \" .. sender() .. \"!\")",
+                             "!script add louder local s = get(\"s\") .. \"!\" set(\"s\", s) respond(s)"])]
+    }
+
+    fn handlers(&self) -> Hooks {
+        vec![Hook::register_msg(cmd!(run))]
+    }
+}
+
+fn find(e: &Event, name: &str) -> Result<Option<Script>, CommandError> {
+    Ok(e.load(Script::in_room(&room_key(&e.chatroom)))?.into_iter()
+        .find(|s: &Script| s.name == name))
+}
+
+/// Scripts answer to whatever isn't a built-in command.
+fn run(e: &Event) -> Outcome {
+    if e.sender == NAME.as_str() {
+        return Ok(vec![])
+    }
+    let name = match e.invocation() {
+        Some((cmd, _)) => cmd.to_lowercase(),
+        None => return Ok(vec![])
+    };
+    if e.commands().routes(&name) || config::is_disabled(e, &name)? {
+        return Ok(vec![])
+    }
+    if let Some(s) = find(e, &name)? {
+        script::run(e, &s.name, &s.code)?;
+    }
+    Ok(vec![])
+}
+
+pub fn script(e: &Event) -> Outcome {
+    let args = e.args();
+    let name = args.str("name").unwrap_or("").trim_left_matches('!').to_lowercase();
+    match args.sub() {
+        "add" => add(e, &name, args.str("code").unwrap_or(""))?,
+        "remove" => remove(e, &name)?,
+        "show" => {
+            let s = find(e, &name)?.ok_or_else(||
+                CommandError::NotFound(format!("There's no {} script here.", name)))?;
+            e.respond(format!("<b>!{}</b> by {}: <code>{}</code>", s.name, s.author, s.code));
+        },
+        _ => {
+            let names = e.load(Script::in_room(&room_key(&e.chatroom)))?.into_iter()
+                .map(|s: Script| s.name)
+                .collect::<Vec<_>>();
+            if names.is_empty() {
+                e.respond_highlight("There are no scripts here.");
+            } else {
+                e.respond_highlight(format!("Scripts here: {}", names.join(", ")));
+            }
+        }
+    };
+    Ok(vec![])
+}
+
+fn add(e: &Event, n: &str, source: &str) -> Result<(), CommandError> {
+    use ::db::scripts::dsl::*;
+
+    if n.is_empty() || !n.chars().all(|c: char| c.is_alphanumeric() || c == '_' || c == '-') {
+        return Err(CommandError::Usage("Script names are letters, numbers, - and _.".to_string()))
+    }
+    if e.commands().find(n).is_some() {
+        return Err(CommandError::Denied(format!("!{} is already a command.", n)))
+    }
+    if e.commands().routes(n) {
+        return Err(CommandError::Denied(format!("!{} is already a factoid.", n)))
+    }
+    script::check(source)
+        .map_err(|why| CommandError::Usage(format!("That doesn't compile: {}", why)))?;
+    e.execute(diesel::insert_or_replace(&NewScript {
+        channel: room_key(&e.chatroom),
+        name: n.to_string(),
+        code: source.to_string(),
+        author: e.sender.to_string(),
+    }).into(scripts::table()))?;
+    e.respond_highlight(format!("Saved !{}.", n));
+    Ok(())
+}
+
+fn remove(e: &Event, n: &str) -> Result<(), CommandError> {
+    use diesel::{ExpressionMethods,FilterDsl};
+
+    let room = room_key(&e.chatroom);
+    let affected = e.execute(diesel::delete(Script::in_room(&room).filter(::db::scripts::dsl::name.eq(n.to_string()))))?;
+    if affected == 0 {
+        return Err(CommandError::NotFound(format!("There's no {} script here.", n)))
+    }
+    e.execute(diesel::delete(Datum::in_room(&room).filter(::db::script_data::dsl::script.eq(n.to_string()))))?;
+    e.respond_highlight(format!("Removed !{}.", n));
+    Ok(())
+}
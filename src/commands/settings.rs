@@ -0,0 +1,149 @@
+use commands::prelude::*;
+use config::{self,default_triggers};
+use config::models::{Disabled,NewDisabled,NewTrigger,Trigger};
+use diesel;
+use diesel::associations::HasTable;
+use permissions::room_key;
+
+pub struct Settings;
+
+impl Plugin for Settings {
+    fn name(&self) -> &'static str {
+        "config"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("config", cmd!(config)).needs(Level::Admin)
+                 .category("Admin").summary("Changes the triggers and commands used in this room.")
+                 .args("triggers | trigger add <prefix> | trigger remove <prefix> | trigger reset | \
+                        disabled | disable <command> | enable <command>")
+                 .examples(&["!config trigger add ?", "!config disable ww"])]
+    }
+}
+
+pub fn config(e: &Event) -> Outcome {
+    let args = e.args();
+    match args.sub() {
+        "triggers" => {
+            let t = config::triggers(e)?.iter().map(|t| format!("<code>{}</code>", t)).collect::<Vec<_>>();
+            e.respond_highlight(format!("Triggers here are: {}", t.join(" ")));
+        },
+        "trigger add" => trigger_add(e, args.str("prefix").unwrap_or(""))?,
+        "trigger remove" => trigger_remove(e, args.str("prefix").unwrap_or(""))?,
+        "trigger reset" => {
+            e.execute(diesel::delete(Trigger::in_room(&room_key(&e.chatroom))))?;
+            e.respond_highlight("Triggers are back to the defaults.");
+        },
+        "disabled" => {
+            let d = config::disabled(e)?;
+            if d.is_empty() {
+                e.respond_highlight("Every command is enabled here.");
+            } else {
+                e.respond_highlight(format!("Disabled here: {}", d.join(", ")));
+            }
+        },
+        "disable" => disable(e, args.str("command").unwrap_or(""))?,
+        "enable" => enable(e, args.str("command").unwrap_or(""))?,
+        _ => {}
+    };
+    Ok(vec![])
+}
+
+/// Rooms start out with the default triggers, so the first change makes
+/// them their own.
+fn own_triggers(e: &Event) -> Result<(), CommandError> {
+    use ::db::triggers::dsl::*;
+
+    let room = room_key(&e.chatroom);
+    if !e.load::<_, Trigger>(Trigger::in_room(&room))?.is_empty() {
+        return Ok(())
+    }
+    for t in default_triggers().into_iter() {
+        e.execute(diesel::insert_or_replace(&NewTrigger { channel: room.clone(), prefix: t })
+            .into(triggers::table()))?;
+    }
+    Ok(())
+}
+
+fn trigger_add(e: &Event, p: &str) -> Result<(), CommandError> {
+    use ::db::triggers::dsl::*;
+
+    own_triggers(e)?;
+    e.execute(diesel::insert_or_replace(&NewTrigger { channel: room_key(&e.chatroom), prefix: p.to_string() })
+        .into(triggers::table()))?;
+    e.respond_highlight(format!("Added <code>{}</code>.", p));
+    Ok(())
+}
+
+fn trigger_remove(e: &Event, p: &str) -> Result<(), CommandError> {
+    use ::db::triggers::dsl::*;
+    use diesel::{ExpressionMethods,FilterDsl};
+
+    let current = config::triggers(e)?;
+    if !current.iter().any(|t| t == p) {
+        return Err(CommandError::NotFound(format!("<code>{}</code> isn't a trigger here.", p)))
+    }
+    if current.len() == 1 {
+        return Err(CommandError::Denied("That's the last trigger; add another one first.".to_string()))
+    }
+
+    own_triggers(e)?;
+    e.execute(diesel::delete(Trigger::in_room(&room_key(&e.chatroom)).filter(prefix.eq(p.to_string()))))?;
+    e.respond_highlight(format!("Removed <code>{}</code>.", p));
+    Ok(())
+}
+
+fn disable(e: &Event, cmd: &str) -> Result<(), CommandError> {
+    use ::db::disabled_commands::dsl::*;
+
+    match e.commands().find(cmd) {
+        Some(s) if s.name == "config" => {
+            Err(CommandError::Denied("Then nobody could turn it back on.".to_string()))
+        },
+        Some(s) => {
+            e.execute(diesel::insert_or_replace(&NewDisabled { channel: room_key(&e.chatroom), name: s.name.to_string() })
+                .into(disabled_commands::table()))?;
+            e.respond_highlight(format!("Disabled !{} here.", s.name));
+            Ok(())
+        },
+        None => Err(CommandError::NotFound(format!("There's no {} command.", cmd))),
+    }
+}
+
+fn enable(e: &Event, cmd: &str) -> Result<(), CommandError> {
+    use ::db::disabled_commands::dsl::*;
+    use diesel::{ExpressionMethods,FilterDsl};
+
+    let n = e.commands().find(cmd).map(|s| s.name).unwrap_or(cmd);
+    let affected = e.execute(diesel::delete(Disabled::in_room(&room_key(&e.chatroom)).filter(name.eq(n.to_string()))))?;
+    if affected == 0 {
+        return Err(CommandError::NotFound(format!("!{} wasn't disabled here.", n)))
+    }
+    e.respond_highlight(format!("Enabled !{} here.", n));
+    Ok(())
+}
+
+#[test]
+fn settings_first_change_copies_the_defaults() {
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_msg(&mq, "hi");
+    trigger_add(&e, "?").unwrap();
+    let mut expected = default_triggers();
+    expected.push("?".to_string());
+    assert_eq!(config::triggers(&e).unwrap(), expected);
+}
+
+#[test]
+fn settings_keep_the_last_trigger() {
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_msg(&mq, "hi");
+    for t in default_triggers().iter().skip(1) {
+        trigger_remove(&e, t).unwrap();
+    }
+    assert_eq!(config::triggers(&e).unwrap(), vec!["!".to_string()]);
+    match trigger_remove(&e, "!") {
+        Err(CommandError::Denied(_)) => {},
+        _ => panic!("removed the last trigger"),
+    }
+    assert_eq!(config::triggers(&e).unwrap(), vec!["!".to_string()]);
+}
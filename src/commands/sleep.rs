@@ -1,39 +1,68 @@
+use args::MAX_DURATION;
 use commands::prelude::*;
+use futures::Future;
+use futures::future::Either;
+use futures::sync::oneshot;
+use std::sync::{Arc,Mutex};
+use tokio_core::reactor::Timeout;
 
-static mut N: Option<Instant> = None;
+/// Ends the current nap early, if there is one.
+type Nap = Arc<Mutex<Option<oneshot::Sender<()>>>>;
 
-pub fn wakeup(e: &Event) -> Hooks {
-    if let Some(i) = unsafe {
-        if let Some(n) = N {
-            N = None;
-            Some(n)
-        } else {
-            None
-        }
-    } {
-        e.cancel(i);
+pub struct Sleep {
+    nap: Nap,
+}
+
+impl Sleep {
+    pub fn new() -> Self {
+        Sleep { nap: Arc::new(Mutex::new(None)) }
+    }
+}
+
+impl Plugin for Sleep {
+    fn name(&self) -> &'static str {
+        "sleep"
+    }
+
+    fn commands(&self) -> Hooks {
+        let (n1, n2) = (self.nap.clone(), self.nap.clone());
+        vec![Hook::register("sleep", |_| box move |e| sleep(e, &n1))
+                 .category("Fun").summary("Naps, then says so.").args("<time:duration>")
+                 .examples(&["!sleep 90", "!sleep 2m"])
+                 .timeout(Duration::from_secs(MAX_DURATION)),
+             Hook::register("wakeup", |_| box move |e| wakeup(e, &n2))
+                 .category("Fun").summary("Ends a nap early.")]
+    }
+}
+
+fn wakeup(e: &Event, nap: &Nap) -> Outcome {
+    if let Some(tx) = nap.lock().unwrap().take() {
+        let _ = tx.send(());
         e.respond("Ok, I'm awake!");
     } else {
         e.respond("I wasn't sleeping!");
     }
-    vec![]
+    Ok(vec![])
 }
 
-pub fn sleep(e: &Event) -> Hooks {
-    if e.message.len() < 7 {
-        return vec![];
-    }
-    match (&e.message[7..]).parse() {
-        Ok(i) => {
-            e.respond(format!("Sleeping for {} seconds. ZZZzzz...", i));
-            let at = e.respond_in("Waking up!", Duration::new(i, 0));
-            unsafe {
-                N = Some(at);
-            }
-        },
-        Err(_) => {
-            e.respond("That doesn't look like a number.");
-        }
+/// Naps on the reactor, so `!wakeup` can end it; a new nap ends the last.
+fn sleep(e: &Event, nap: &Nap) -> Outcome {
+    let d = match e.args().duration("time") {
+        Some(d) => d,
+        None => return Ok(vec![])
     };
-    vec![]
+    let timer = Timeout::new(d, &e.handle()).map_err(|err|
+        CommandError::Internal(format!("can't start a timer: {}", err)))?;
+    let (tx, woken) = oneshot::channel();
+    *nap.lock().unwrap() = Some(tx);
+    e.respond(format!("Sleeping for {} seconds. ZZZzzz...", d.as_secs()));
+
+    let (e, nap) = (e.clone(), nap.clone());
+    Ok(vec![Hook::later(timer.select2(woken).then(move |r| {
+        if let Ok(Either::A(_)) = r {
+            nap.lock().unwrap().take();
+            e.respond("Waking up!");
+        }
+        Ok::<Hooks, CommandError>(vec![])
+    }))])
 }
@@ -2,8 +2,29 @@ use commands::prelude::*;
 use diesel;
 use diesel::associations::HasTable;
 
+pub struct Welcomes;
+
+impl Plugin for Welcomes {
+    fn name(&self) -> &'static str {
+        "welcome"
+    }
+
+    fn commands(&self) -> Hooks {
+        vec![Hook::register("welcome", cmd!(welcome))
+                 .category("Fun").summary("Sets what I say when you join.")
+                 .args("get | set <welcome...> | clear")
+                 .examples(&["!welcome set Hello again!"])]
+    }
+
+    fn handlers(&self) -> Hooks {
+        vec![Hook::join(cmd!(say_welcome))]
+    }
+}
+
 mod models {
     use ::db::welcomes;
+    use damnpacket::Username;
+    use permissions::user_key;
 
     #[derive(Queryable,Debug)]
     pub struct Welcome {
@@ -19,8 +40,17 @@ mod models {
         pub body: String,
     }
 
+    impl NewWelcome {
+        pub fn new(u: &Username, body: &str) -> Self {
+            NewWelcome {
+                user: user_key(u),
+                body: body.to_string(),
+            }
+        }
+    }
+
     impl Welcome {
-        pub fn belongs_to(u: &[u8]) -> ::diesel::helper_types::FindBy<
+        pub fn belongs_to(u: &Username) -> ::diesel::helper_types::FindBy<
                 ::db::welcomes::dsl::welcomes,
                 ::db::welcomes::dsl::user,
                 String> {
@@ -29,56 +59,61 @@ mod models {
             use diesel::FilterDsl;
             use diesel::ExpressionMethods;
 
-            welcomes.filter(user.eq(string!(u)))
+            welcomes.filter(user.eq(user_key(u)))
         }
     }
 }
 
-pub fn say_welcome(e: &Event) -> Hooks {
+pub fn say_welcome(e: &Event) -> Outcome {
     use self::models::Welcome;
 
-    if let Some(w) = e.load(Welcome::belongs_to(&e.sender))
+    if let Some(w) = e.load(Welcome::belongs_to(&e.sender))?
                       .into_iter().next() as Option<Welcome> {
         e.respond(w.body);
     }
 
-    vec![]
+    Ok(vec![])
 }
 
-pub fn welcome(e: &Event) -> Hooks {
+pub fn welcome(e: &Event) -> Outcome {
     use self::models::{NewWelcome,Welcome};
     use ::db::welcomes::dsl::*;
 
-    match word(&e.content()) {
-        ("get", _) => {
-            match e.load(Welcome::belongs_to(&e.sender)).into_iter().next() as Option<Welcome> {
+    let args = e.args();
+    match args.sub() {
+        "get" => {
+            match e.load(Welcome::belongs_to(&e.sender))?.into_iter().next() as Option<Welcome> {
                 Some(w) => e.respond_highlight(format!("Your welcome is '{}'", w.body)),
-                None => e.respond_highlight("You don't have a welcome."),
+                None => return Err(CommandError::NotFound("You don't have a welcome.".to_string())),
             };
         },
-        ("set", "") => {
-            e.respond_highlight("Usage: !welcome set <b>thing</b><br>\
-                                If you want no welcome, use !welcome clear.");
-        },
-        ("set", x) => {
-            e.execute(diesel::insert_or_replace(&NewWelcome {
-                user: string!(e.sender),
-                body: x.to_string()
-            }).into(welcomes::table()));
+        "set" => {
+            e.execute(diesel::insert_or_replace(&NewWelcome::new(&e.sender, args.str("welcome").unwrap_or("")))
+                .into(welcomes::table()))?;
             e.respond_highlight("Your welcome has been set.");
         },
-        ("clear", _) => {
-            let affected = e.execute(diesel::delete(Welcome::belongs_to(&e.sender)));
-            if affected > 0 {
-                e.respond_highlight("Your welcome has been forgotten.");
-            } else {
-                e.respond_highlight("You didn't have a welcome.");
+        "clear" => {
+            let affected = e.execute(diesel::delete(Welcome::belongs_to(&e.sender)))?;
+            if affected == 0 {
+                return Err(CommandError::NotFound("You didn't have a welcome.".to_string()))
             }
+            e.respond_highlight("Your welcome has been forgotten.");
         },
-        (_, _) => {
-            e.respond_highlight("Usage: !welcome { get | set <i>welcome</i> | clear }");
-        },
+        _ => {}
     };
 
-    vec![]
+    Ok(vec![])
+}
+
+#[test]
+fn welcome_ignores_case() {
+    use self::models::{NewWelcome,Welcome};
+    use damnpacket::Username;
+
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_event(&mq, "recv chat:room\n\nmsg main\nfrom=Alice\n\nhi\0");
+    e.execute(diesel::insert_or_replace(&NewWelcome::new(&Username::from("ALICE"), "Hi!"))
+        .into(::db::welcomes::table)).unwrap();
+    let found = e.load(Welcome::belongs_to(&e.sender)).unwrap() as Vec<Welcome>;
+    assert_eq!(found.into_iter().map(|w| w.body).collect::<Vec<_>>(), vec!["Hi!".to_string()]);
 }
@@ -3,14 +3,14 @@ use chrono::Duration;
 use chrono::Local;
 use chrono::Timelike;
 use commands::prelude::*;
+use damnpacket::Username;
 use nom::digit;
-use state::Storage;
 use std::collections::{HashMap,HashSet};
 use std::time::Duration as StdDuration;
-use std::sync::{Mutex,MutexGuard};
+use std::sync::{Arc,Mutex};
 
-fn until(other: DateTime<Local>) -> Option<StdDuration> {
-    let d = other.signed_duration_since(Local::now());
+fn until(other: DateTime<Local>, now: DateTime<Local>) -> Option<StdDuration> {
+    let d = other.signed_duration_since(now);
     if d < Duration::zero() {
         return None
     }
@@ -18,11 +18,48 @@ fn until(other: DateTime<Local>) -> Option<StdDuration> {
     Some(StdDuration::new(d.num_seconds() as u64, nanos_only.num_nanoseconds().unwrap() as u32))
 }
 
-static _WARS: Storage<Mutex<HashMap<W, War>>> = Storage::new();
+type Wars = Arc<Mutex<HashMap<W, War>>>;
 
-#[allow(non_snake_case)]
-fn WARS<'a>() -> MutexGuard<'a, HashMap<W, War>> {
-    _WARS.get().lock().unwrap()
+pub struct WordWar {
+    wars: Wars,
+    max_minutes: i64,
+}
+
+impl WordWar {
+    pub fn new() -> Self {
+        WordWar {
+            wars: Arc::new(Mutex::new(HashMap::new())),
+            max_minutes: 59,
+        }
+    }
+}
+
+impl Plugin for WordWar {
+    fn name(&self) -> &'static str {
+        "wordwar"
+    }
+
+    /// Wars can be made shorter than an hour with `WORDWAR_MAX_MINUTES`.
+    fn init(&mut self, ctx: &Context) {
+        if let Some(m) = ctx.setting("max_minutes").and_then(|m| m.parse().ok()) {
+            self.max_minutes = m;
+        }
+    }
+
+    fn commands(&self) -> Hooks {
+        let (w1, w2, w3) = (self.wars.clone(), self.wars.clone(), self.wars.clone());
+        let max = self.max_minutes;
+        vec![Hook::register("in", |_| box move |e| join(e, &w1)).only(Scope::Public)
+                 .category("Fun").summary("Joins a word war before it starts.")
+                 .args("[<id>]"),
+             Hook::register("out", |_| box move |e| leave(e, &w2)).only(Scope::Public)
+                 .category("Fun").summary("Leaves a word war.")
+                 .args("[<id>]"),
+             Hook::register("ww", |_| box move |e| wordwar(e, &w3, max)).only(Scope::Public)
+                 .category("Fun").summary("Runs word wars.")
+                 .args("at <when...> | cancel <id> | [list]")
+                 .examples(&["!ww at :30 for 15"])]
+    }
 }
 
 named!(dec<u32>, map_res!(map_res!(digit, ::std::str::from_utf8), ::std::str::FromStr::from_str));
@@ -42,18 +79,17 @@ pub struct War {
     pub minutes: i64,
     pub start_msg: Option<Instant>,
     pub end_msg: Option<Instant>,
-    pub participants: HashSet<String>,
-    pub starter: String,
+    pub participants: HashSet<Username>,
+    pub starter: Username,
 }
 
 impl War {
-    pub fn parse(bytes: &[u8]) -> Result<(DateTime<Local>, DateTime<Local>, i64), String> {
+    pub fn parse(bytes: &[u8], current_time: DateTime<Local>, max: i64) -> Result<(DateTime<Local>, DateTime<Local>, i64), String> {
         let (at, dur) = parse_ww(bytes).to_full_result()
             .map_err(|_|format!("Usage: !ww at :<b>time</b> for <b>minutes</b>"))?;
-        if dur > 59 {
+        if dur as i64 > max {
             return Err("Too many minutes.".to_string())
         }
-        let current_time = Local::now();
         let start_time = if current_time.minute() >= at {
             current_time + Duration::hours(1)
         } else {
@@ -62,17 +98,19 @@ impl War {
         Ok((start_time, start_time + Duration::minutes(dur as i64), dur as i64))
     }
 
-    pub fn register_msgs(&mut self, e: &Event) {
+    pub fn register_msgs(&mut self, e: &Event) -> Result<(), CommandError> {
         self.cancel(e);
-        let participants_list = self.participants.clone().into_iter().collect::<Vec<_>>().join(", ");
-        let start = until(self.start_time).map(|t| {
-            e.respond_in(format!("{}: <b>START WRITING!</b>", participants_list), t)
-        });
-        let end = until(self.end_time).map(|t| {
-            e.respond_in(format!("{}: <b>STOP WRITING!</b>", participants_list), t)
-        });
-        self.start_msg = start;
-        self.end_msg = end;
+        let participants_list = self.participants.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+        let now = e.local_now();
+        self.start_msg = match until(self.start_time, now) {
+            Some(t) => Some(e.respond_in(format!("{}: <b>START WRITING!</b>", participants_list), t)?),
+            None => None
+        };
+        self.end_msg = match until(self.end_time, now) {
+            Some(t) => Some(e.respond_in(format!("{}: <b>STOP WRITING!</b>", participants_list), t)?),
+            None => None
+        };
+        Ok(())
     }
 
     pub fn cancel(&self, e: &Event) {
@@ -81,43 +119,40 @@ impl War {
     }
 }
 
-pub fn wordwar(e: &Event) -> Hooks {
-    match word(e.content()) {
-        ("at", rest) => wordwar_at(e, rest),
-        ("cancel", id) => wordwar_cancel(e, id),
-        ("list", _) => wordwar_list(e),
-        x => { e.respond_highlight(format!("{:?}", x)); vec![] }
+fn wordwar(e: &Event, wars: &Wars, max: i64) -> Outcome {
+    let args = e.args();
+    match args.sub() {
+        "at" => wordwar_at(e, wars, args.str("when").unwrap_or(""), max),
+        "cancel" => wordwar_cancel(e, wars, args.str("id").unwrap_or("")),
+        _ => wordwar_list(e, wars),
     }
 }
 
-fn wordwar_cancel(e: &Event, id: &str) -> Hooks {
-    match id.parse() {
-        Ok(h) => {
-            let mut wars_guard = WARS();
-            match wars_guard.get(&h).cloned() {
-                Some(w) => if w.starter == string!(e.sender) {
-                    let war = wars_guard.remove(&h).unwrap();
-                    war.cancel(&e);
-                    e.respond_highlight(format!("Canceled war #{}.", h))
-                } else {
-                    e.respond_highlight("That's not yours.")
-                },
-                None => e.respond_highlight("No war with that ID found.")
-            }
+fn wordwar_cancel(e: &Event, wars: &Wars, id: &str) -> Outcome {
+    let h = id.parse()
+        .map_err(|_| CommandError::Usage("That doesn't look like a war ID.".to_string()))?;
+    let mut wars_guard = wars.lock().unwrap();
+    match wars_guard.get(&h).cloned() {
+        Some(w) => if w.starter == e.sender {
+            let war = wars_guard.remove(&h).unwrap();
+            war.cancel(&e);
+            e.respond_highlight(format!("Canceled war #{}.", h));
+            Ok(vec![])
+        } else {
+            Err(CommandError::Denied("That's not yours.".to_string()))
         },
-        Err(_) => e.respond_highlight("That doesn't look like a war ID."),
-    };
-    vec![]
+        None => Err(CommandError::NotFound("No war with that ID found.".to_string()))
+    }
 }
 
-fn wordwar_list(e: &Event) -> Hooks {
+fn wordwar_list(e: &Event, wars: &Wars) -> Outcome {
     let mut response = "<ul>".to_string();
-    for (k, v) in WARS().iter() {
-        if Local::now() > v.end_time {
+    let now = e.local_now();
+    for (k, v) in wars.lock().unwrap().iter() {
+        if now > v.end_time {
             continue
         }
 
-        let now = Local::now();
         let seconds_so_far = if now < v.start_time {
             0
         } else {
@@ -141,85 +176,161 @@ fn wordwar_list(e: &Event) -> Hooks {
     response.push_str("</ul>");
     e.respond(response);
 
-    vec![]
-}
-
-fn wordwar_at(e: &Event, rest: &str) -> Hooks {
-    let res = War::parse(rest.as_bytes());
-    match res {
-        Ok((start_instant, end_instant, minutes)) => {
-            let w = W::next();
-
-            e.respond_highlight(format!("Scheduled war with ID #{}.", w));
-
-            let w2 = w.clone();
-
-            let mut new_war = War {
-                start_time: start_instant,
-                end_time: end_instant,
-                minutes: minutes,
-                start_msg: None,
-                end_msg: None,
-                participants: {
-                    let mut h = HashSet::new();
-                    h.insert(string!(e.sender));
-                    h
-                },
-                starter: string!(e.sender),
-            };
-            new_war.register_msgs(&e);
-
-            let start_cloned = new_war.start_msg.clone().unwrap();
-            let end_cloned = new_war.end_msg.clone().unwrap();
-
-            WARS().insert(w, new_war);
-
-            return vec![Hook::register("in", |m| box move |e|
-                if Instant::now() > start_cloned {
-                    vec![Hook::unregister(m)]
-                } else {
-                    match WARS().get_mut(&w) {
-                        None => return vec![Hook::unregister(m)],
-                        Some(mut current_war) => {
-                            if current_war.participants.contains(&string!(e.sender)) {
-                                e.respond_highlight("You're already in this war.");
-                            } else {
-                                current_war.participants.insert(string!(e.sender));
-                                current_war.register_msgs(&e);
-                                e.respond_highlight(format!("You've been added to war #{}.", w2));
-                            }
-                        }
-                    }
-
-                    vec![]
-                }
-            ), Hook::register("out", |m| box move |e|
-                if Instant::now() > end_cloned {
-                    vec![Hook::unregister(m)]
-                } else {
-                    match WARS().get_mut(&w) {
-                        None => return vec![Hook::unregister(m)],
-                        Some(mut current_war) => {
-                            if current_war.participants.contains(&string!(e.sender)) {
-                                current_war.participants.remove(&string!(e.sender));
-                                current_war.register_msgs(&e);
-                                e.respond_highlight(format!("You've been removed from war #{}.", w2));
-                            } else {
-                                e.respond_highlight("You're not in this war.");
-                            }
-                        }
-                    }
-
-                    vec![]
-                }
-            )]
+    Ok(vec![])
+}
+
+fn wordwar_at(e: &Event, wars: &Wars, rest: &str, max: i64) -> Outcome {
+    let (start_instant, end_instant, minutes) = War::parse(rest.as_bytes(), e.local_now(), max)
+        .map_err(CommandError::Usage)?;
+    let w = W::next();
+
+    e.respond_highlight(format!("Scheduled war with ID #{}.", w));
+
+    let mut new_war = War {
+        start_time: start_instant,
+        end_time: end_instant,
+        minutes: minutes,
+        start_msg: None,
+        end_msg: None,
+        participants: {
+            let mut h = HashSet::new();
+            h.insert(e.sender.clone());
+            h
         },
-        Err(s) => { e.respond_highlight(s); }
+        starter: e.sender.clone(),
+    };
+    new_war.register_msgs(&e)?;
+
+    wars.lock().unwrap().insert(w, new_war);
+
+    Ok(vec![])
+}
+
+/// The war someone means by `!in` or `!out`: the one they name, or else
+/// the only one they could mean.
+fn choose<F>(e: &Event, wars: &HashMap<W, War>, none: &str, open: F) -> Result<W, CommandError>
+    where F: Fn(&War) -> bool {
+    match e.args().str("id") {
+        Some(id) => match id.trim_left_matches('#').parse::<W>() {
+            Ok(w) if wars.get(&w).map(|x| open(x)).unwrap_or(false) => Ok(w),
+            _ => Err(CommandError::NotFound("No open war with that ID found.".to_string())),
+        },
+        None => {
+            let candidates = wars.iter().filter(|&(_, x)| open(x)).map(|(w, _)| *w).collect::<Vec<_>>();
+            match candidates.len() {
+                0 => Err(CommandError::NotFound(none.to_string())),
+                1 => Ok(candidates[0]),
+                _ => Err(CommandError::Usage("Which war? Tell me its ID.".to_string())),
+            }
+        }
     }
+}
+
+fn join(e: &Event, wars: &Wars) -> Outcome {
+    let now = e.local_now();
+    let mut wars = wars.lock().unwrap();
+    let w = choose(e, &wars, "There's no war to join.", |x| now < x.start_time)?;
+    let war = wars.get_mut(&w).unwrap();
+    if war.participants.contains(&e.sender) {
+        e.respond_highlight("You're already in this war.");
+    } else {
+        war.participants.insert(e.sender.clone());
+        war.register_msgs(&e)?;
+        e.respond_highlight(format!("You've been added to war #{}.", w));
+    }
+    Ok(vec![])
+}
+
+fn leave(e: &Event, wars: &Wars) -> Outcome {
+    let now = e.local_now();
+    let mut wars = wars.lock().unwrap();
+    let w = choose(e, &wars, "You're not in any war.",
+                   |x| now < x.end_time && x.participants.contains(&e.sender))?;
+    let war = wars.get_mut(&w).unwrap();
+    war.participants.remove(&e.sender);
+    war.register_msgs(&e)?;
+    e.respond_highlight(format!("You've been removed from war #{}.", w));
+    Ok(vec![])
+}
+
+#[cfg(test)]
+fn noon() -> DateTime<Local> {
+    use chrono::TimeZone;
+    Local.ymd(2017, 5, 1).and_hms(12, 10, 30)
+}
+
+#[test]
+fn war_starts_later_this_hour() {
+    use chrono::TimeZone;
+
+    let (start, end, minutes) = War::parse(b":30 for 15", noon(), 59).unwrap();
+    assert_eq!(start, Local.ymd(2017, 5, 1).and_hms(12, 30, 0));
+    assert_eq!(end, Local.ymd(2017, 5, 1).and_hms(12, 45, 0));
+    assert_eq!(minutes, 15);
+}
+
+#[test]
+fn war_wraps_to_next_hour() {
+    use chrono::TimeZone;
+
+    let (start, end, _) = War::parse(b":05 for 10", noon(), 59).unwrap();
+    assert_eq!(start, Local.ymd(2017, 5, 1).and_hms(13, 5, 0));
+    assert_eq!(end, Local.ymd(2017, 5, 1).and_hms(13, 15, 0));
+}
 
-    vec![]
+#[test]
+fn war_rejects_long_wars() {
+    assert_eq!(War::parse(b":30 for 60", noon(), 59), Err("Too many minutes.".to_string()));
 }
 
-pub fn wars_init() {
-    _WARS.set(Mutex::new(HashMap::new()));
+#[test]
+fn war_messages_fire_on_the_clock() {
+    use clock::{Clock,ManualClock};
+
+    let clock = ManualClock::new(noon());
+    let (start, end, _) = War::parse(b":30 for 15", clock.local(), 59).unwrap();
+    assert_eq!(until(start, clock.local()), Some(StdDuration::from_secs(19 * 60 + 30)));
+
+    clock.advance(StdDuration::from_secs(20 * 60));
+    assert_eq!(until(start, clock.local()), None);
+    assert_eq!(until(end, clock.local()), Some(StdDuration::from_secs(14 * 60 + 30)));
+
+    clock.advance(StdDuration::from_secs(15 * 60));
+    assert_eq!(until(end, clock.local()), None);
+}
+
+#[test]
+fn war_messages_are_queued_on_the_clock() {
+    use clock::Clock;
+    use event::test_event;
+    use messagequeue::test_queue;
+
+    let (_core, clock, mq) = test_queue();
+    let e = test_event(&mq, "recv chat:room\n\nmsg main\nfrom=alice\n\n!ww at :30 for 15\0");
+    let (start, end, minutes) = War::parse(b":30 for 15", clock.local(), 59).unwrap();
+    let mut war = War {
+        start_time: start,
+        end_time: end,
+        minutes: minutes,
+        start_msg: None,
+        end_msg: None,
+        participants: vec![e.sender.clone()].into_iter().collect(),
+        starter: e.sender.clone(),
+    };
+    let queued = || mq.jobs().into_iter()
+        .map(|(_, at, job)| (at - clock.now(), job.description))
+        .collect::<Vec<_>>();
+
+    war.register_msgs(&e).unwrap();
+    assert_eq!(queued(), vec![
+        (StdDuration::from_secs(30 * 60), "alice: <b>START WRITING!</b>".to_string()),
+        (StdDuration::from_secs(45 * 60), "alice: <b>STOP WRITING!</b>".to_string())]);
+
+    // Someone joining replaces the messages rather than adding more.
+    war.participants.insert(Username::from("bob"));
+    war.register_msgs(&e).unwrap();
+    let mut said = queued().into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+    said.sort();
+    assert_eq!(said.len(), 2);
+    assert!(said.iter().all(|d| d.contains("alice") && d.contains("bob")));
 }
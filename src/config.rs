@@ -0,0 +1,127 @@
+use diesel::QueryResult;
+use event::Event;
+use handler::NAME;
+use permissions::room_key;
+
+pub mod models {
+    use ::db::{disabled_commands,triggers};
+
+    #[derive(Queryable,Debug)]
+    pub struct Trigger {
+        id: i32,
+        pub channel: String,
+        pub prefix: String,
+    }
+
+    #[derive(Insertable,Debug)]
+    #[table_name="triggers"]
+    pub struct NewTrigger {
+        pub channel: String,
+        pub prefix: String,
+    }
+
+    #[derive(Queryable,Debug)]
+    pub struct Disabled {
+        id: i32,
+        pub channel: String,
+        pub name: String,
+    }
+
+    #[derive(Insertable,Debug)]
+    #[table_name="disabled_commands"]
+    pub struct NewDisabled {
+        pub channel: String,
+        pub name: String,
+    }
+
+    impl Trigger {
+        pub fn in_room(room: &str) -> ::diesel::helper_types::FindBy<
+                ::db::triggers::dsl::triggers,
+                ::db::triggers::dsl::channel,
+                String> {
+            use ::db::triggers::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            triggers.filter(channel.eq(room.to_string()))
+        }
+    }
+
+    impl Disabled {
+        pub fn in_room(room: &str) -> ::diesel::helper_types::FindBy<
+                ::db::disabled_commands::dsl::disabled_commands,
+                ::db::disabled_commands::dsl::channel,
+                String> {
+            use ::db::disabled_commands::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            disabled_commands.filter(channel.eq(room.to_string()))
+        }
+    }
+}
+
+/// What rooms without triggers of their own use: `!`, and the bot's name
+/// followed by a colon or comma.
+pub fn default_triggers() -> Vec<String> {
+    vec!["!".to_string(), format!("{}:", *NAME), format!("{},", *NAME)]
+}
+
+/// The prefixes that mark a command in the event's room.
+pub fn triggers(e: &Event) -> QueryResult<Vec<String>> {
+    use self::models::Trigger;
+
+    let mine = e.load(Trigger::in_room(&room_key(&e.chatroom)))?.into_iter()
+        .map(|t: Trigger| t.prefix)
+        .collect::<Vec<_>>();
+    if mine.is_empty() {
+        Ok(default_triggers())
+    } else {
+        Ok(mine)
+    }
+}
+
+/// Commands switched off in the event's room.
+pub fn disabled(e: &Event) -> QueryResult<Vec<String>> {
+    use self::models::Disabled;
+
+    Ok(e.load(Disabled::in_room(&room_key(&e.chatroom)))?.into_iter()
+        .map(|d: Disabled| d.name)
+        .collect())
+}
+
+pub fn is_disabled(e: &Event, name: &str) -> QueryResult<bool> {
+    Ok(disabled(e)?.iter().any(|d| d == name))
+}
+
+#[test]
+fn config_default_triggers() {
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_msg(&mq, "hi");
+    assert_eq!(triggers(&e).unwrap(), default_triggers());
+}
+
+#[test]
+fn config_disabled_commands_dont_run() {
+    use self::models::NewDisabled;
+    use diesel;
+    use hooks::{counting_hook,Hook,HookStorage};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_msg(&mq, "!disabletest");
+    let ran = Rc::new(Cell::new(0));
+    let mut hooks = HookStorage::new();
+    hooks.apply(vec![Hook::register("disabletest", |_| counting_hook(&ran))]);
+    hooks.dispatch(&e);
+    assert_eq!(ran.get(), 1);
+
+    e.execute(diesel::insert(&NewDisabled { channel: room_key(&e.chatroom), name: "disabletest".to_string() })
+        .into(::db::disabled_commands::table)).unwrap();
+    assert!(is_disabled(&e, "disabletest").unwrap());
+    hooks.dispatch(&e);
+    assert_eq!(ran.get(), 1);
+}
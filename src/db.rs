@@ -1 +1,19 @@
 infer_schema!("dotenv:DATABASE_URL");
+
+/// An empty in-memory database with every migration run, for tests.
+#[cfg(test)]
+pub fn test_db() -> ::diesel::sqlite::SqliteConnection {
+    use diesel::Connection;
+    use diesel::connection::SimpleConnection;
+
+    let conn = ::diesel::sqlite::SqliteConnection::establish(":memory:").unwrap();
+    for up in [include_str!("../migrations/20170501041154_create_welcomes/up.sql"),
+               include_str!("../migrations/20170612193012_create_grants/up.sql"),
+               include_str!("../migrations/20170618140521_create_channel_config/up.sql"),
+               include_str!("../migrations/20170624170233_create_scripts/up.sql"),
+               include_str!("../migrations/20170626093410_create_factoids/up.sql"),
+               include_str!("../migrations/20170701120000_fold_welcome_users/up.sql")].iter() {
+        conn.batch_execute(up).unwrap();
+    }
+    conn
+}
@@ -1,74 +1,184 @@
-use damnpacket::{Message,MessageBody,MessageIsh};
-use diesel::ExecuteDsl;
+use args::Args;
+use channel::{Channel,Channels};
+use chrono::{DateTime,Local};
+use config;
+use damnpacket::{ChannelName,Message,MessageBody,MessageIsh,Username};
+use diesel::{ExecuteDsl,QueryResult};
 use diesel::sqlite::SqliteConnection;
-use messagequeue::MessageQueue;
+use handler::NAME;
+use hooks::{self,CommandError,Commands};
+use messagequeue::{Job,JobId,MessageQueue};
+use moderation;
+use std::cell::{Ref,RefCell};
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::env;
 use std::time::{Duration,Instant};
 use std::rc::Rc;
+use tokio_core::reactor::Handle;
 use diesel::LoadDsl;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminOp {
+    Create, Update, Rename, Move, Remove, Show, Privclass
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EType {
-    Join, Part, Message, Action
+    Join, Part, Message, Action,
+    Kick, Privchg, Admin(AdminOp), Topic, Title,
 }
 
+/// Who's who for each event type:
+///
+/// * `Join`, `Part`: `sender` came or went, `message` is the part reason.
+/// * `Kick`: `sender` was kicked `by` someone, `message` is the reason.
+/// * `Privchg`: `sender` was moved `by` someone to privclass `message`.
+/// * `Admin`: `sender` did it; `p`, `name`, `prev`, `n`, `privs` and `e` are
+///   in `attrs`, and `message` is the body of `admin show`.
+/// * `Topic`, `Title`: `sender` set it to `message`.
 #[derive(Clone)]
 pub struct Event {
     pub ty: EType,
-    pub chatroom: Vec<u8>,
-    pub sender: Vec<u8>,
+    pub chatroom: ChannelName,
+    pub sender: Username,
     pub message: String,
+    pub by: Option<Username>,
+    pub attrs: HashMap<Vec<u8>, String>,
 
     connection: Rc<SqliteConnection>,
 
     mq: MessageQueue,
+
+    channels: Channels,
+
+    commands: Commands,
+
+    /// Set by `HookStorage` for commands that declare their arguments.
+    args: RefCell<Args>,
+
+    /// The room's triggers, loaded the first time they're needed.
+    triggers: RefCell<Option<Vec<String>>>,
 }
 
-impl<'a> TryFrom<(&'a Message, Rc<SqliteConnection>, MessageQueue)> for Event {
-    type Error = ();
-
-    fn try_from(arg: (&'a Message, Rc<SqliteConnection>, MessageQueue)) -> Result<Self, ()> {
-        let (msg, conn, mq) = arg;
-        let chatroom = msg.argument.clone();
-        for sub in msg.submessage().into_iter() {
-            return match sub.name.as_ref().map(|x|x.as_slice()) {
-                Some(b"msg") => Ok(Event {
-                    ty: EType::Message,
-                    chatroom: chatroom.expect("invariant: recv msg, no chatroom"),
-                    sender: sub.get_attr("from").expect("invariant: recv msg, no sender").as_bytes().to_vec(),
-                    message: sub.body.map(|x|x.to_string()).unwrap_or("".to_string()),
-                    connection: conn,
-                    mq: mq,
-                }),
-                Some(b"action") => Ok(Event {
-                    ty: EType::Action,
-                    chatroom: chatroom.expect("invariant: recv action, no chatroom"),
-                    sender: sub.get_attr("from").expect("invariant: recv action, no sender").as_bytes().to_vec(),
-                    message: sub.body.map(|x|x.to_string()).unwrap_or("".to_string()),
-                    connection: conn,
-                    mq: mq,
-                }),
-                Some(b"join") => Ok(Event {
-                    ty: EType::Join,
-                    chatroom: chatroom.expect("invariant: recv join, no chatroom"),
-                    sender: sub.argument.clone().expect("invariant: recv join, no sender"),
-                    message: "".to_string(),
-                    connection: conn,
-                    mq: mq,
-                }),
-                Some(b"part") => Ok(Event {
-                    ty: EType::Part,
-                    chatroom: chatroom.expect("invariant: recv part, no chatroom"),
-                    sender: sub.argument.clone().expect("invariant: recv part, no sender"),
-                    message: "".to_string(),
-                    connection: conn,
-                    mq: mq,
-                }),
-                _ => Err(())
-            }
+/// Why a packet couldn't be turned into an `Event`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventError {
+    /// The packet had no body, or its body didn't parse as a subpacket.
+    NoSubpacket,
+    /// A well-formed packet we don't model, e.g. `recv foo` or `property p=members`.
+    Unsupported(String),
+    /// A required argument or attribute wasn't there.
+    Missing { packet: &'static str, field: &'static str },
+    /// The packet's argument isn't a `chat:` or `pchat:` namespace.
+    BadNamespace(String),
+}
+
+impl ::std::fmt::Display for EventError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match *self {
+            EventError::NoSubpacket => write!(f, "no subpacket"),
+            EventError::Unsupported(ref k) => write!(f, "unsupported packet '{}'", k),
+            EventError::Missing { packet, field } => write!(f, "{} is missing its {}", packet, field),
+            EventError::BadNamespace(ref n) => write!(f, "bad namespace '{}'", n),
+        }
+    }
+}
+
+fn need<T>(x: Option<T>, packet: &'static str, field: &'static str) -> Result<T, EventError> {
+    x.ok_or(EventError::Missing { packet: packet, field: field })
+}
+
+fn namespace(x: Option<&Vec<u8>>, packet: &'static str) -> Result<ChannelName, EventError> {
+    let raw = need(x, packet, "chatroom")?;
+    ChannelName::parse(raw).ok_or_else(|| EventError::BadNamespace(string!(raw)))
+}
+
+impl<'a> TryFrom<(&'a Message, Rc<SqliteConnection>, MessageQueue, Channels, Commands)> for Event {
+    type Error = EventError;
+
+    fn try_from(arg: (&'a Message, Rc<SqliteConnection>, MessageQueue, Channels, Commands)) -> Result<Self, EventError> {
+        let (msg, conn, mq, channels, commands) = arg;
+        let mk = move |ty, chatroom, sender, message| Event {
+            ty: ty,
+            chatroom: chatroom,
+            sender: sender,
+            message: message,
+            by: None,
+            attrs: HashMap::new(),
+            connection: conn,
+            mq: mq,
+            channels: channels,
+            commands: commands,
+            args: RefCell::new(Args::default()),
+            triggers: RefCell::new(None),
+        };
+
+        if msg.name == b"property" {
+            let ty = match msg.get_attr("p") {
+                Some("topic") => EType::Topic,
+                Some("title") => EType::Title,
+                p => return Err(EventError::Unsupported(format!("property {}", p.unwrap_or(""))))
+            };
+            return Ok(mk(ty,
+                namespace(msg.argument.as_ref(), "property")?,
+                Username::from(msg.get_attr("by").unwrap_or("")),
+                msg.body.as_ref().map(|x|x.to_string()).unwrap_or("".to_string())));
+        }
+
+        let chatroom = namespace(msg.argument.as_ref(), "recv")?;
+        let sub = msg.submessage().ok_or(EventError::NoSubpacket)?;
+        let body = sub.body.as_ref().map(|x|x.to_string()).unwrap_or("".to_string());
+        let from = |packet| need(sub.get_attr("from"), packet, "sender").map(Username::from);
+        let user = |packet| need(sub.argument.as_ref(), packet, "user").map(|x| Username::from(&x[..]));
+        match sub.name.as_ref().map(|x|x.as_slice()) {
+            Some(b"msg") => Ok(mk(EType::Message, chatroom, from("recv msg")?, body)),
+            Some(b"action") => Ok(mk(EType::Action, chatroom, from("recv action")?, body)),
+            Some(b"join") => Ok(mk(EType::Join, chatroom, user("recv join")?, "".to_string())),
+            Some(b"part") => Ok(mk(EType::Part, chatroom, user("recv part")?,
+                sub.get_attr("r").unwrap_or("").to_string())),
+            Some(b"kicked") => {
+                let mut ev = mk(EType::Kick, chatroom, user("recv kicked")?, body);
+                ev.by = sub.get_attr("by").map(Username::from);
+                Ok(ev)
+            },
+            Some(b"privchg") => {
+                let pc = need(sub.get_attr("pc"), "recv privchg", "privclass")?.to_string();
+                let mut ev = mk(EType::Privchg, chatroom, user("recv privchg")?, pc);
+                ev.by = sub.get_attr("by").map(Username::from);
+                Ok(ev)
+            },
+            Some(b"admin") => {
+                let op = match sub.argument.as_ref().map(|x|x.as_slice()) {
+                    Some(b"create") => AdminOp::Create,
+                    Some(b"update") => AdminOp::Update,
+                    Some(b"rename") => AdminOp::Rename,
+                    Some(b"move") => AdminOp::Move,
+                    Some(b"remove") => AdminOp::Remove,
+                    Some(b"show") => AdminOp::Show,
+                    Some(b"privclass") => AdminOp::Privclass,
+                    x => return Err(EventError::Unsupported(
+                        format!("recv admin {}", x.map(|a| string!(a)).unwrap_or(String::new()))))
+                };
+                let mut ev = mk(EType::Admin(op), chatroom,
+                    Username::from(sub.get_attr("by").unwrap_or("")), body);
+                ev.attrs = sub.attrs.clone();
+                Ok(ev)
+            },
+            x => Err(EventError::Unsupported(
+                format!("recv {}", x.map(|a| string!(a)).unwrap_or(String::new()))))
         }
-        Err(())
+    }
+}
+
+/// A `send` of `kind` (`msg`, `action` or `npmsg`) to `room`.
+pub fn chat_packet<S>(room: &ChannelName, kind: &str, msg: S) -> Message
+    where S: Into<String> {
+    Message {
+        name: b"send".to_vec(),
+        argument: Some(room.as_bytes()),
+        attrs: HashMap::new(),
+        body: Some(MessageBody::from(format!("{} main\n\n{}\0", kind, msg.into())))
     }
 }
 
@@ -79,18 +189,140 @@ pub fn word<'a>(s: &'a str) -> (&'a str, &'a str) {
 }
 
 impl Event {
-    fn mk<S>(&self, msg: S) -> Message
+    fn job<S>(&self, room: &ChannelName, msg: S) -> Job
         where S: Into<String> {
-        Message {
-            name: b"send".to_vec(),
-            argument: Some(self.chatroom.clone()),
-            attrs: HashMap::new(),
-            body: Some(MessageBody::from(format!("msg main\n\n{}\0", msg.into())))
+        let msg = msg.into();
+        Job::new(self.mk(room, "msg", msg.clone()))
+            .owner(self.sender.clone())
+            .channel(room.clone())
+            .description(msg)
+    }
+
+    fn mk<S>(&self, room: &ChannelName, kind: &str, msg: S) -> Message
+        where S: Into<String> {
+        chat_packet(room, kind, msg)
+    }
+
+    fn moderate(&self, msg: Message, description: String) -> Instant {
+        let job = Job::new(msg)
+            .owner(self.sender.clone())
+            .channel(self.chatroom.clone())
+            .description(description)
+            .watch();
+        self.mq.submit(job, self.now()).1
+    }
+
+    pub fn kick(&self, user: &Username, reason: Option<&str>) -> Instant {
+        self.moderate(moderation::kick(&self.chatroom, user, reason), format!("kick {}", user))
+    }
+
+    pub fn ban(&self, user: &Username) -> Instant {
+        self.moderate(moderation::ban(&self.chatroom, user), format!("ban {}", user))
+    }
+
+    pub fn unban(&self, user: &Username) -> Instant {
+        self.moderate(moderation::unban(&self.chatroom, user), format!("unban {}", user))
+    }
+
+    pub fn promote(&self, user: &Username, privclass: Option<&str>) -> Instant {
+        self.moderate(moderation::promote(&self.chatroom, user, privclass), format!("promote {}", user))
+    }
+
+    pub fn demote(&self, user: &Username, privclass: Option<&str>) -> Instant {
+        self.moderate(moderation::demote(&self.chatroom, user, privclass), format!("demote {}", user))
+    }
+
+    pub fn set_topic(&self, text: &str) -> Instant {
+        self.moderate(moderation::set(&self.chatroom, "topic", text), "set the topic".to_string())
+    }
+
+    pub fn set_title(&self, text: &str) -> Instant {
+        self.moderate(moderation::set(&self.chatroom, "title", text), "set the title".to_string())
+    }
+
+    /// What we know of the room this happened in; empty if we know nothing.
+    /// Every command routed here, for looking them up by name.
+    pub fn commands(&self) -> &Commands {
+        &self.commands
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channels.get(&self.chatroom).unwrap_or_default()
+    }
+
+    pub fn sender_privclass(&self) -> Option<String> {
+        self.channel().member(&self.sender).map(|m| m.privclass.clone())
+    }
+
+    /// The order of the sender's privclass here; higher means more trusted.
+    pub fn sender_order(&self) -> Option<u32> {
+        let channel = self.channel();
+        channel.member(&self.sender).and_then(|m| channel.privclass_order(&m.privclass))
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.chatroom.is_private()
+    }
+
+    /// Answers in a pchat with the sender, joining it first if need be.
+    pub fn reply_privately<S>(&self, msg: S) -> Instant
+        where S: Into<String> {
+        if self.is_private() {
+            return self.respond(msg)
+        }
+        let room = ChannelName::private(Username::from(NAME.as_str()), self.sender.clone());
+        if self.channels.get(&room).is_none() {
+            self.mq.push(Message {
+                name: b"join".to_vec(),
+                argument: Some(room.as_bytes()),
+                attrs: HashMap::new(),
+                body: None,
+            });
         }
+        self.mq.push(self.mk(&room, "msg", msg))
     }
 
+    pub fn attr(&self, k: &str) -> Option<&str> {
+        self.attrs.get(k.as_bytes()).map(|x|x.as_str())
+    }
+
+    /// The command and its arguments, if the message starts with one of
+    /// this room's triggers.
+    pub fn invocation<'a>(&'a self) -> Option<(&'a str, &'a str)> {
+        if self.triggers.borrow().is_none() {
+            let triggers = config::triggers(self).unwrap_or_else(|e| {
+                warn!("can't load triggers, using the defaults: {}", e);
+                config::default_triggers()
+            });
+            *self.triggers.borrow_mut() = Some(triggers);
+        }
+        hooks::invocation(&self.message, self.triggers.borrow().as_ref().unwrap())
+    }
+
+    /// Everything after the command, whichever trigger was used.
     pub fn content<'a>(&'a self) -> &'a str {
-        word(&self.message).1
+        self.invocation().map(|x| x.1).unwrap_or_else(|| word(&self.message).1)
+    }
+
+    pub fn args(&self) -> Ref<Args> {
+        self.args.borrow()
+    }
+
+    pub fn set_args(&self, args: Args) {
+        *self.args.borrow_mut() = args;
+    }
+
+    pub fn now(&self) -> Instant {
+        self.mq.now()
+    }
+
+    pub fn local_now(&self) -> DateTime<Local> {
+        self.mq.local_now()
+    }
+
+    /// The reactor, for timers and other futures to hand to `Hook::later`.
+    pub fn handle(&self) -> Handle {
+        self.mq.handle()
     }
 
     pub fn cancel(&self, i: Instant) -> Option<Message> {
@@ -99,35 +331,305 @@ impl Event {
 
     pub fn respond<S>(&self, msg: S) -> Instant
         where S: Into<String> {
-        self.mq.clone().push(self.mk(msg))
+        self.mq.clone().push(self.mk(&self.chatroom, "msg", msg))
+    }
+
+    /// Like `/me`.
+    pub fn act<S>(&self, msg: S) -> Instant
+        where S: Into<String> {
+        self.mq.push(self.mk(&self.chatroom, "action", msg))
+    }
+
+    /// Sends `msg` without the server turning `:icon:`s and the like into tablumps.
+    pub fn npmsg<S>(&self, msg: S) -> Instant
+        where S: Into<String> {
+        self.mq.push(self.mk(&self.chatroom, "npmsg", msg))
     }
 
-    pub fn respond_in<S>(&self, msg: S, d: Duration) -> Instant
+    pub fn respond_to<S>(&self, room: &ChannelName, msg: S) -> Instant
         where S: Into<String> {
-        self.mq.clone().schedule(self.mk(msg), d)
+        self.mq.push(self.mk(room, "msg", msg))
+    }
+
+    pub fn respond_in<S>(&self, msg: S, d: Duration) -> Result<Instant, CommandError>
+        where S: Into<String> {
+        let at = self.after(d)?;
+        Ok(self.respond_at(msg, at))
     }
 
     pub fn respond_at<S>(&self, msg: S, i: Instant) -> Instant
         where S: Into<String> {
-        self.mq.clone().schedule_at(self.mk(msg), i)
+        self.mq.submit(self.job(&self.chatroom, msg), i).1
+    }
+
+    /// Repeat `msg` every `d`, starting `d` from now, until cancelled.
+    pub fn respond_every<S>(&self, msg: S, d: Duration) -> Result<JobId, CommandError>
+        where S: Into<String> {
+        let at = self.after(d)?;
+        Ok(self.mq.submit(self.job(&self.chatroom, msg).every(d), at).0)
+    }
+
+    /// `d` from now, unless that's past the end of time as `Instant` sees it.
+    fn after(&self, d: Duration) -> Result<Instant, CommandError> {
+        self.now().checked_add(d).ok_or_else(||
+            CommandError::Usage("That's further off than I can count.".to_string()))
+    }
+
+    pub fn jobs(&self) -> Vec<(JobId, Instant, Job)> {
+        self.mq.jobs()
+    }
+
+    pub fn cancel_job(&self, id: JobId) -> Option<Job> {
+        self.mq.cancel(id)
+    }
+
+    pub fn cancel_jobs_of(&self, owner: &Username) -> usize {
+        self.mq.cancel_owner(owner)
+    }
+
+    pub fn cancel_jobs_in(&self, channel: &ChannelName) -> usize {
+        self.mq.cancel_channel(channel)
+    }
+
+    pub fn with_filter<T, R, F>(&self, f: F) -> Option<R>
+        where T: ::std::any::Any,
+              F: FnOnce(&mut T) -> R {
+        self.mq.with_filter(f)
+    }
+
+    pub fn is_owner(&self) -> bool {
+        env::var("OWNER").map(|o| self.sender == o.as_str()).unwrap_or(false)
     }
 
     pub fn respond_highlight<S>(&self, msg: S) -> Instant
         where S: Into<String> {
-        self.respond(format!("{}: {}", string!(self.sender), msg.into()))
+        self.respond(format!("{}: {}", self.sender, msg.into()))
+    }
+
+    /// Asks the sender something, and hands their next message in this room
+    /// to `then`, which can ask again for questions that come in steps. An
+    /// answer doesn't reach any other hook, even one `then` fails on; then
+    /// the sender is told why and can answer again. If they don't answer
+    /// within `timeout`, they're told it's too late, unless the bot has left
+    /// the room by then.
+    pub fn ask<S, F>(&self, prompt: S, timeout: Duration, then: F) -> Result<hooks::Hook, CommandError>
+        where S: Into<String>,
+              F: Fn(&Event) -> hooks::Outcome + 'static {
+        use hooks::Hook;
+
+        let late = self.respond_in(format!("{}: Never mind, then.", self.sender), timeout)?;
+        self.respond_highlight(prompt);
+        let (sender, room) = (self.sender.clone(), self.chatroom.clone());
+        Ok(Hook::register_msg(move |_| box move |e| {
+            if e.sender != sender || e.chatroom != room {
+                return Ok(vec![Hook::Ignored])
+            }
+            let mut hooks = match then(e) {
+                Ok(hooks) => hooks,
+                Err(err) => {
+                    hooks::report(Some(e), "ask", &err);
+                    // Ignored, so it's not spent and they can try again.
+                    return Ok(vec![Hook::Ignored, Hook::Consume])
+                }
+            };
+            e.cancel(late);
+            hooks.push(Hook::Consume);
+            Ok(hooks)
+        }).priority(hooks::FILTER)
+            .once()
+            .late(late)
+            .while_in(self.chatroom.clone()))
+    }
+
+    pub fn db(&self) -> &SqliteConnection {
+        &self.connection
     }
 
-    pub fn load<T,U>(&self, x: T) -> Vec<U>
+    pub fn load<T,U>(&self, x: T) -> QueryResult<Vec<U>>
         where T: LoadDsl<SqliteConnection> + ::std::fmt::Debug,
               U: ::diesel::Queryable<T::SqlType,::diesel::sqlite::Sqlite>,
               ::diesel::sqlite::Sqlite: ::diesel::types::HasSqlType<T::SqlType> {
         info!("diesel: {:?}", x);
-        x.load(&self.connection).expect("Unable to load SQL")
+        x.load(&self.connection)
     }
 
-    pub fn execute<T>(&self, x: T) -> usize
+    pub fn execute<T>(&self, x: T) -> QueryResult<usize>
         where T: ExecuteDsl<SqliteConnection> + ::std::fmt::Debug {
         info!("diesel: {:?}", x);
-        x.execute(&self.connection).expect("Unable to insert SQL")
+        x.execute(&self.connection)
     }
 }
+
+#[cfg(test)]
+fn parse_event_in(core: &::tokio_core::reactor::Core, raw: &'static str) -> Result<Event, EventError> {
+    Event::try_from((&Message::from(raw), Rc::new(::db::test_db()), MessageQueue::new(&core.handle()), Channels::new(), Commands::new(false)))
+}
+
+/// The event in `raw`, sending through `mq`, for tests outside this module.
+#[cfg(test)]
+pub fn test_event(mq: &MessageQueue, raw: &str) -> Event {
+    Event::try_from((&Message::from(raw.as_bytes()), Rc::new(::db::test_db()), mq.clone(), Channels::new(), Commands::new(false))).unwrap()
+}
+
+/// alice saying `text` in #room.
+#[cfg(test)]
+pub fn test_msg(mq: &MessageQueue, text: &str) -> Event {
+    test_event(mq, &format!("recv chat:room\n\nmsg main\nfrom=alice\n\n{}\0", text))
+}
+
+#[cfg(test)]
+fn parse_event(raw: &'static str) -> Result<Event, EventError> {
+    parse_event_in(&::tokio_core::reactor::Core::new().unwrap(), raw)
+}
+
+/// Everything `f` queued, in order, as raw packets.
+#[cfg(test)]
+fn sent<F>(f: F) -> Vec<Vec<u8>>
+    where F: FnOnce(&Event) {
+    let core = ::tokio_core::reactor::Core::new().unwrap();
+    let ev = parse_event_in(&core, "recv chat:room\n\nmsg main\nfrom=alice\n\nhi\0").unwrap();
+    f(&ev);
+    ev.jobs().into_iter().map(|(_, _, job)| job.message.as_bytes()).collect()
+}
+
+#[test]
+fn event_kicked() {
+    let ev = parse_event("recv chat:room\n\nkicked alice\nby=bob\n\ntoo loud\0").unwrap();
+    assert_eq!(ev.ty, EType::Kick);
+    assert_eq!(ev.sender, "alice");
+    assert_eq!(ev.by, Some(Username::from("bob")));
+    assert_eq!(ev.message, "too loud");
+}
+
+#[test]
+fn event_part_reason() {
+    let ev = parse_event("recv chat:room\n\npart alice\nr=timed out\n\0").unwrap();
+    assert_eq!(ev.ty, EType::Part);
+    assert_eq!(ev.message, "timed out");
+}
+
+#[test]
+fn event_privchg() {
+    let ev = parse_event("recv chat:room\n\nprivchg alice\nby=bob\npc=Operators\n\0").unwrap();
+    assert_eq!(ev.ty, EType::Privchg);
+    assert_eq!(ev.message, "Operators");
+    assert_eq!(ev.by, Some(Username::from("bob")));
+}
+
+#[test]
+fn event_admin_rename() {
+    let ev = parse_event("recv chat:room\n\nadmin rename\np=privclass\nby=bob\nprev=Ops\nname=Operators\n\0").unwrap();
+    assert_eq!(ev.ty, EType::Admin(AdminOp::Rename));
+    assert_eq!(ev.sender, "bob");
+    assert_eq!(ev.attr("prev"), Some("Ops"));
+    assert_eq!(ev.attr("name"), Some("Operators"));
+}
+
+#[test]
+fn event_topic() {
+    let ev = parse_event("property chat:room\np=topic\nby=bob\nts=1493600000\n\nwelcome!\0").unwrap();
+    assert_eq!(ev.ty, EType::Topic);
+    assert_eq!(ev.sender, "bob");
+    assert_eq!(ev.message, "welcome!");
+    assert!(parse_event("property chat:room\np=members\n\n\0").is_err());
+}
+
+#[test]
+fn event_malformed() {
+    assert_eq!(parse_event("recv chat:room\n\0").err(), Some(EventError::NoSubpacket));
+    assert_eq!(parse_event("recv\n\nmsg main\nfrom=alice\n\nhi\0").err(),
+               Some(EventError::Missing { packet: "recv", field: "chatroom" }));
+    assert_eq!(parse_event("recv chat:room\n\nmsg main\n\nhi\0").err(),
+               Some(EventError::Missing { packet: "recv msg", field: "sender" }));
+    assert_eq!(parse_event("recv chat:room\n\njoin\ns=0\n\n\0").err(),
+               Some(EventError::Missing { packet: "recv join", field: "user" }));
+    assert_eq!(parse_event("recv chat:room\n\nprivchg alice\nby=bob\n\0").err(),
+               Some(EventError::Missing { packet: "recv privchg", field: "privclass" }));
+    assert_eq!(parse_event("recv chat:room\n\nadmin explode\n\0").err(),
+               Some(EventError::Unsupported("recv admin explode".to_string())));
+    assert_eq!(parse_event("recv chat:room\n\nwhat ever\n\0").err(),
+               Some(EventError::Unsupported("recv what".to_string())));
+    assert_eq!(parse_event("recv login:room\n\nmsg main\nfrom=alice\n\nhi\0").err(),
+               Some(EventError::BadNamespace("login:room".to_string())));
+}
+
+#[test]
+fn event_private() {
+    let ev = parse_event("recv pchat:alice:participle\n\nmsg main\nfrom=alice\n\nhi\0").unwrap();
+    assert!(ev.is_private());
+    assert_eq!(ev.chatroom.other(&Username::from(NAME.as_str())), Some(&Username::from("alice")));
+    assert!(!parse_event("recv chat:room\n\nmsg main\nfrom=alice\n\nhi\0").unwrap().is_private());
+}
+
+#[test]
+fn event_outgoing_kinds() {
+    assert_eq!(sent(|e| { e.respond("hi & bye"); }),
+               vec![b"send chat:room\n\nmsg main\n\nhi &amp; bye\0".to_vec()]);
+    assert_eq!(sent(|e| { e.act("waves"); }),
+               vec![b"send chat:room\n\naction main\n\nwaves\0".to_vec()]);
+    assert_eq!(sent(|e| { e.npmsg(":wave:"); }),
+               vec![b"send chat:room\n\nnpmsg main\n\n:wave:\0".to_vec()]);
+    assert_eq!(sent(|e| { e.respond_to(&"#other".parse().unwrap(), "psst"); }),
+               vec![b"send chat:other\n\nmsg main\n\npsst\0".to_vec()]);
+}
+
+#[test]
+fn event_reply_privately() {
+    assert_eq!(sent(|e| { e.reply_privately("psst"); }),
+               vec![b"join pchat:alice:participle\n\0".to_vec(),
+                    b"send pchat:alice:participle\n\nmsg main\n\npsst\0".to_vec()]);
+}
+
+#[test]
+fn event_ask() {
+    use hooks::{Hook,HookStorage,counting_hook};
+    use std::cell::{Cell,RefCell};
+
+    let (_core, clock, mq) = ::messagequeue::test_queue();
+    let timeout = Duration::from_secs(60);
+    let pending = || mq.jobs().into_iter()
+        .filter(|&(_, _, ref job)| String::from_utf8_lossy(&job.message.as_bytes()).contains("Never mind, then."))
+        .count();
+    let answers = Rc::new(RefCell::new(vec![]));
+    let seen = Rc::new(Cell::new(0));
+    let ask = |hooks: &mut HookStorage| {
+        let a = answers.clone();
+        hooks.apply(vec![test_msg(&mq, "!poll").ask("What's the question?", timeout, move |e| {
+            if e.message == "dunno" {
+                return Err(CommandError::Usage("Ask something.".to_string()))
+            }
+            a.borrow_mut().push(e.message.clone());
+            Ok(vec![])
+        }).unwrap()]);
+    };
+
+    let mut hooks = HookStorage::new();
+    hooks.apply(vec![Hook::register_msg(|_| counting_hook(&seen))]);
+    ask(&mut hooks);
+    assert_eq!(pending(), 1);
+    hooks.dispatch(&test_event(&mq, "recv chat:room\n\nmsg main\nfrom=bob\n\nnot yours\0"));
+    hooks.dispatch(&test_event(&mq, "recv chat:other\n\nmsg main\nfrom=alice\n\nwrong room\0"));
+    assert_eq!(seen.get(), 2);
+    // A failed answer is still taken, and they can answer again.
+    hooks.dispatch(&test_msg(&mq, "dunno"));
+    assert_eq!((answers.borrow().len(), seen.get(), pending()), (0, 2, 1));
+    hooks.dispatch(&test_msg(&mq, "Pie or cake?"));
+    assert_eq!(*answers.borrow(), vec!["Pie or cake?".to_string()]);
+    assert_eq!((seen.get(), pending()), (2, 0));
+    hooks.dispatch(&test_msg(&mq, "already answered"));
+    assert_eq!((answers.borrow().len(), seen.get()), (1, 3));
+
+    // Too late: the answer isn't taken, and they're told so.
+    ask(&mut hooks);
+    clock.advance(timeout);
+    hooks.dispatch(&test_msg(&mq, "Pie."));
+    assert_eq!((answers.borrow().len(), seen.get(), pending()), (1, 4, 1));
+
+    // Leaving the room drops the question without telling anyone.
+    ask(&mut hooks);
+    assert_eq!(pending(), 2);
+    hooks.left(&ChannelName::Chat("room".to_string()), &mq);
+    assert_eq!(pending(), 1);
+    hooks.dispatch(&test_msg(&mq, "Pie."));
+    assert_eq!((answers.borrow().len(), seen.get()), (1, 5));
+}
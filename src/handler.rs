@@ -1,35 +1,46 @@
-use damnpacket::Message;
+use channel::Channels;
+use damnpacket::{ChannelName,Message};
 use damnpacket::MessageIsh;
-use hooks::{Hooks,HookStorage};
+use hooks::HookStorage;
 use messagequeue::MessageQueue;
 use std::collections::HashMap;
-use event::{Event,EType};
+use event::{Event,EventError,chat_packet};
 use std::convert::TryFrom;
+use std::env;
 use std::rc::Rc;
 use diesel::sqlite::SqliteConnection;
 
-type Callback = fn(Message, MessageQueue, &mut HookStorage, &Rc<SqliteConnection>);
+type Callback = fn(Message, MessageQueue, &mut HookStorage, &Rc<SqliteConnection>, &Channels);
 
 lazy_static! {
+    /// The bot's username, from `BOT_NAME`.
+    pub static ref NAME: String = env::var("BOT_NAME").unwrap_or("participle".to_string());
+
     pub static ref ACTIONS: HashMap<&'static [u8], Callback> = {
         let mut m = HashMap::new();
         m.insert(&b"dAmnServer"[..], respond_damnserver as Callback);
+        m.insert(&b"kick"[..], respond_error as Callback);
+        m.insert(&b"kicked"[..], respond_kicked as Callback);
         m.insert(&b"login"[..], respond_login as Callback);
+        m.insert(&b"part"[..], respond_part as Callback);
         m.insert(&b"ping"[..], respond_ping as Callback);
+        m.insert(&b"property"[..], respond_recv as Callback);
         m.insert(&b"recv"[..], respond_recv as Callback);
+        m.insert(&b"send"[..], respond_error as Callback);
+        m.insert(&b"set"[..], respond_error as Callback);
         m
     };
 }
 
-fn respond_ping(_: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>) {
+fn respond_ping(_: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>, _: &Channels) {
     mq.push(Message::from("pong\n\0"));
 }
 
-fn respond_damnserver(_: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>) {
-    mq.push(Message::from(concat!("login participle\npk=", env!("PK"), "\n\0")));
+fn respond_damnserver(_: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>, _: &Channels) {
+    mq.push(Message::from(format!("login {}\npk={}\n\0", *NAME, env!("PK")).as_bytes()));
 }
 
-fn respond_login(msg: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>) {
+fn respond_login(msg: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>, _: &Channels) {
     match msg.get_attr(&b"e"[..]) {
         Some("ok") => {
             info!("Logged in successfully");
@@ -40,19 +51,54 @@ fn respond_login(msg: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<Sql
     };
 }
 
-fn respond_recv(msg: Message, mq: MessageQueue, h: &mut HookStorage, s: &Rc<SqliteConnection>) {
-    if let Ok(ev) = Event::try_from((&msg, s.clone(), mq)) {
-        let updates = match ev.ty {
-            EType::Join => h.join_iter().flat_map(|cmd| {
-                cmd(&ev)
-            }).collect::<Hooks>(),
-            EType::Part => {
-                vec![]
-            },
-            _ => h.msg_iter().flat_map(|cmd| {
-                cmd(&ev)
-            }).collect::<Hooks>()
-        };
-        h.apply(updates);
+fn respond_part(msg: Message, mq: MessageQueue, h: &mut HookStorage, _: &Rc<SqliteConnection>, c: &Channels) {
+    let room = msg.argument.as_ref().and_then(|r| ChannelName::parse(r));
+    if let (Some(room), Some("ok")) = (room, msg.get_attr("e")) {
+        info!("Left {}", room);
+        c.forget(&room);
+        h.left(&room, &mq);
+    }
+}
+
+fn respond_kicked(msg: Message, mq: MessageQueue, h: &mut HookStorage, _: &Rc<SqliteConnection>, c: &Channels) {
+    if let Some(room) = msg.argument.as_ref().and_then(|r| ChannelName::parse(r)) {
+        warn!("Kicked from {} by {:?}", room, msg.get_attr("by"));
+        c.forget(&room);
+        h.left(&room, &mq);
+    }
+}
+
+/// `kick`, `send` and `set` only come back from the server when they fail.
+fn respond_error(msg: Message, mq: MessageQueue, _: &mut HookStorage, _: &Rc<SqliteConnection>, _: &Channels) {
+    let err = match msg.get_attr("e") {
+        Some(e) if e != "ok" => e,
+        _ => return
+    };
+    let room = match msg.argument.as_ref().and_then(|r| ChannelName::parse(r)) {
+        Some(r) => r,
+        None => return
+    };
+    match mq.failed(&msg.name, &room) {
+        Some(job) => {
+            let who = job.owner.map(|o| format!("{}: ", o)).unwrap_or(String::new());
+            mq.push(chat_packet(&room, "msg", format!("{}I couldn't {} ({}).", who, job.description, err)));
+        },
+        None => warn!("{} in {} failed: {}", string!(msg.name), room, err)
     }
 }
+
+fn respond_recv(msg: Message, mq: MessageQueue, h: &mut HookStorage, s: &Rc<SqliteConnection>, c: &Channels) {
+    c.observe(&msg);
+    let ev = match Event::try_from((&msg, s.clone(), mq, c.clone(), h.commands())) {
+        Ok(ev) => ev,
+        Err(EventError::Unsupported(k)) => {
+            debug!("ignoring {}", k);
+            return
+        },
+        Err(e) => {
+            warn!("dropping malformed event ({}): {:?}", e, msg);
+            return
+        }
+    };
+    h.dispatch(&ev);
+}
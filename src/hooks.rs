@@ -1,68 +1,677 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
+use std::panic::{self,AssertUnwindSafe};
+use std::rc::{Rc,Weak};
 use std::sync::atomic::{AtomicUsize,ATOMIC_USIZE_INIT,Ordering};
-use std::collections::hash_map::Values;
-use event::Event;
+use std::time::{Duration,Instant};
+use args::Syntax;
+use clock::{self,SharedClock};
+use config;
+use damnpacket::ChannelName;
+use diesel;
+use event::{EType,Event,word};
+use futures::{Future,Stream};
+use handler::NAME;
+use messagequeue::MessageQueue;
+use metrics;
+use std::fmt;
+use permissions::{self,Level};
+use router::Router;
+use tasks::{Ended,Tasks};
+use tokio_core::reactor::{Handle,Interval};
 
-static TRIGGERS: [&'static str; 2] = ["!", "participle: "];
+/// Splits a message that starts with one of `triggers` into the command and
+/// its arguments. The longest trigger wins, and may be followed by spaces.
+pub fn invocation<'a>(msg: &'a str, triggers: &[String]) -> Option<(&'a str, &'a str)> {
+    triggers.iter()
+        .filter(|t| !t.is_empty() && msg.starts_with(t.as_str()))
+        .max_by_key(|t| t.len())
+        .map(|t| word(msg[t.len()..].trim_left()))
+}
+
+/// Why a command didn't do what it was asked. `HookStorage` tells the
+/// sender, so commands can just return one.
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    /// The arguments didn't make sense.
+    Usage(String),
+    /// The sender isn't allowed to do that, here or at all.
+    Denied(String),
+    /// What the command was asked about isn't there.
+    NotFound(String),
+    /// Something broke that isn't the sender's fault. Only the log gets
+    /// the details.
+    Internal(String),
+    /// A `Hook::Later` didn't finish in time.
+    TimedOut,
+}
+
+impl CommandError {
+    /// What `metrics` counts it as.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            CommandError::Usage(_) => "usage",
+            CommandError::Denied(_) => "denied",
+            CommandError::NotFound(_) => "not_found",
+            CommandError::Internal(_) => "internal",
+            CommandError::TimedOut => "timeout",
+        }
+    }
+}
+
+/// What the sender is told.
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            CommandError::Usage(ref s) |
+            CommandError::Denied(ref s) |
+            CommandError::NotFound(ref s) => write!(f, "{}", s),
+            CommandError::Internal(_) => write!(f, "Sorry, something went wrong there."),
+            CommandError::TimedOut => write!(f, "That took too long, so I gave up."),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for CommandError {
+    fn from(e: diesel::result::Error) -> Self {
+        CommandError::Internal(format!("database: {}", e))
+    }
+}
 
-pub type Command = Box<Fn(&Event) -> Hooks + Send>;
+pub type Outcome = Result<Hooks, CommandError>;
+
+pub type Command = Box<Fn(&Event) -> Outcome>;
+
+/// Work a command finishes on the reactor; see `Hook::later`.
+pub type Pending = Box<Future<Item = Hooks, Error = CommandError>>;
+
+/// When a hook runs, relative to others that want the same event: higher
+/// first. Ties go to hooks for the event's type, then the command, then
+/// hooks for every event, each oldest first.
+pub type Priority = i32;
+
+/// For hooks that should see events before commands do, like ignore lists
+/// and flood filters.
+pub const FILTER: Priority = 100;
+
+pub const NORMAL: Priority = 0;
+
+/// How `HookStorage` treats a hook that isn't a command; commands keep its
+/// priority in their `Spec`, and last until they're dropped.
+#[derive(Clone, Debug, Default)]
+pub struct Meta {
+    pub priority: Priority,
+    /// How many more runs it gets, not counting ones that return
+    /// `Hook::Ignored`.
+    pub runs: Option<u32>,
+    pub until: Option<Instant>,
+    /// The room it's for; it's dropped when the bot leaves.
+    pub room: Option<ChannelName>,
+    /// A message queued for `until`, which is taken back if the hook is
+    /// dropped because the bot left `room`.
+    pub late: Option<Instant>,
+}
+
+impl Meta {
+    pub fn expired(&self, now: Instant) -> bool {
+        self.until.map(|i| i <= now).unwrap_or(false)
+    }
+
+    /// Counts a run, and says whether that was the last one.
+    fn spend(&mut self) -> bool {
+        match self.runs {
+            Some(ref mut n) => {
+                *n = n.saturating_sub(1);
+                *n == 0
+            },
+            None => false
+        }
+    }
+}
+
+/// How often `HookStorage` drops hooks that have expired, so ones waiting
+/// on an event that never comes don't hang around.
+const EXPIRE_EVERY: u64 = 60;
+
+/// Where a command may be used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    Anywhere, Public, Private
+}
+
+/// Everything `HookStorage` checks before handing a message to a command,
+/// and everything `!help` says about it.
+#[derive(Clone, Copy, Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    pub scope: Scope,
+    pub level: Level,
+    pub category: &'static str,
+    pub summary: &'static str,
+    /// The arguments, as an `args::Syntax` declaration. Commands without
+    /// one get the text after their name unchecked.
+    pub args: Option<&'static str>,
+    pub examples: &'static [&'static str],
+    pub aliases: &'static [&'static str],
+    /// How long its `Hook::later`s get, if not `default_timeout()`.
+    pub timeout: Option<Duration>,
+    pub priority: Priority,
+}
+
+impl Spec {
+    pub fn new(name: &'static str) -> Self {
+        Spec {
+            name: name,
+            scope: Scope::Anywhere,
+            level: Level::Anyone,
+            category: "Other",
+            summary: "",
+            args: None,
+            examples: &[],
+            aliases: &[],
+            timeout: None,
+            priority: NORMAL,
+        }
+    }
+
+    pub fn usage(&self) -> String {
+        match self.args.map(Syntax::parse) {
+            Some(Ok(s)) => s.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn answers_to(&self, cmd: &str) -> bool {
+        self.name == cmd || self.aliases.contains(&cmd)
+    }
+
+    /// Why not, if the sender can't run the command here.
+    fn admits(&self, ev: &Event) -> Result<(), CommandError> {
+        match (self.scope, ev.is_private()) {
+            (Scope::Public, true) => {
+                return Err(CommandError::Denied("That only works in a chatroom.".to_string()))
+            },
+            (Scope::Private, false) => {
+                ev.reply_privately("That only works in a private chat; try it here.");
+                return Err(CommandError::Denied("That only works in a private chat; I've messaged you.".to_string()))
+            },
+            _ => {}
+        }
+        if !permissions::allowed(ev, self.level, self.name)? {
+            return Err(CommandError::Denied(format!("Only {} can do that.", self.level)))
+        }
+        Ok(())
+    }
+}
+
+/// How many times a hook may panic before it's dropped.
+const MAX_PANICS: u32 = 3;
+
+/// How long a `Hook::later` gets when its command doesn't say:
+/// `COMMAND_TIMEOUT_SECS`, or 30 seconds.
+pub fn default_timeout() -> Duration {
+    Duration::from_secs(env::var("COMMAND_TIMEOUT_SECS").ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(30))
+}
+
+/// Any hook in `HookStorage`, whatever kind it is.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HookId {
+    Message(M), Join(J), Part(P), Event(V),
+    Route(String),
+}
+
+impl HookId {
+    /// When it was made, for running hooks oldest first.
+    fn seq(&self) -> usize {
+        match *self {
+            HookId::Message(m) => m.un(),
+            HookId::Join(j) => j.un(),
+            HookId::Part(p) => p.un(),
+            HookId::Event(v) => v.un(),
+            HookId::Route(_) => usize::max_value(),
+        }
+    }
+
+    /// The hook that takes it out again.
+    pub fn drop(self) -> Hook {
+        match self {
+            HookId::Message(m) => Hook::DropMessage(m),
+            HookId::Join(j) => Hook::DropJoin(j),
+            HookId::Part(p) => Hook::DropPart(p),
+            HookId::Event(v) => Hook::DropEvent(v),
+            HookId::Route(name) => Hook::DropRoute(name),
+        }
+    }
+}
+
+/// What a panic said, if it said anything.
+pub fn panic_message(payload: &(Any + Send)) -> &str {
+    payload.downcast_ref::<&str>().map(|s| *s)
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+        .unwrap_or("no message")
+}
+
+/// Which of a priority's hooks go first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Typed, Command, Every,
+}
+
+/// Puts hooks in the order they run; see `Priority`.
+fn in_order<T>(found: &mut Vec<(Priority, Stage, HookId, T)>) {
+    found.sort_by(|a, b| b.0.cmp(&a.0)
+        .then(a.1.cmp(&b.1))
+        .then(a.2.seq().cmp(&b.2.seq())));
+}
+
+/// The commands a `HookStorage` has routed, and the other names routed to
+/// them. Every `Event` carries it, the way it does `Channels`, so commands
+/// like `!help` can look the others up.
+#[derive(Clone)]
+pub struct Commands(Rc<RefCell<Index>>);
+
+struct Index {
+    router: Router<M>,
+    specs: HashMap<M, Spec>,
+}
+
+impl Commands {
+    pub fn new(case_sensitive: bool) -> Self {
+        Commands(Rc::new(RefCell::new(Index {
+            router: Router::new(case_sensitive),
+            specs: HashMap::new(),
+        })))
+    }
+
+    /// The command called `cmd`, or that has it as an alias.
+    pub fn find(&self, cmd: &str) -> Option<Spec> {
+        self.0.borrow().specs.values().find(|s| s.answers_to(cmd)).cloned()
+    }
+
+    /// Whether `!name` reaches anything, including names routed to another
+    /// command, like factoids.
+    pub fn routes(&self, name: &str) -> bool {
+        self.0.borrow().router.route(name).is_some()
+    }
+
+    pub fn all(&self) -> Vec<Spec> {
+        self.0.borrow().specs.values().cloned().collect()
+    }
+
+    fn route(&self, cmd: &str) -> Option<M> {
+        self.0.borrow().router.route(cmd).cloned()
+    }
+
+    fn spec(&self, m: &M) -> Option<Spec> {
+        self.0.borrow().specs.get(m).cloned()
+    }
+}
 
 pub struct HookStorage {
     msg: HashMap<M, Command>,
+    commands: HashMap<M, Command>,
+    index: Commands,
     join: HashMap<J, Command>,
+    part: HashMap<P, Command>,
+    event: HashMap<V, Command>,
+    panics: HashMap<HookId, u32>,
+    meta: HashMap<HookId, Meta>,
+    /// Where `Hook::later`s run, once `run_on` is called.
+    tasks: Option<Tasks>,
+    me: Weak<RefCell<HookStorage>>,
+    /// What the expiry timer checks `Meta::until` against: the same clock
+    /// as the `Event`s `dispatch` gets.
+    clock: SharedClock,
 }
 
 impl HookStorage {
     pub fn new() -> Self {
         HookStorage {
             msg: HashMap::new(),
+            commands: HashMap::new(),
+            index: Commands::new(false),
             join: HashMap::new(),
+            part: HashMap::new(),
+            event: HashMap::new(),
+            panics: HashMap::new(),
+            meta: HashMap::new(),
+            tasks: None,
+            me: Weak::new(),
+            clock: clock::system(),
         }
     }
 
-    fn add_command(&mut self, u: M, s: &'static str, cb: Command) -> M {
-        fn matches(ev: &Event, cmd: &str) -> bool {
-            for t in TRIGGERS.iter() {
-                if ev.message.starts_with(t) {
-                    if (&ev.message[t.len()..]).starts_with(cmd) {
-                        return true
-                    }
+    /// Whether `!Ping` should run `ping`; it does unless this is set.
+    pub fn case_sensitive(mut self, yes: bool) -> Self {
+        self.index = Commands::new(yes);
+        self
+    }
+
+    /// What's routed here, for the `Event`s handed to `dispatch`.
+    pub fn commands(&self) -> Commands {
+        self.index.clone()
+    }
+
+    /// Lets `Hook::later`s run on `h`, applying what they return to `me`,
+    /// which should be this storage. Any still running are dropped along
+    /// with it, so a lost connection takes its commands' work with it.
+    /// Hooks expire by `clock`, which should be the `MessageQueue`'s.
+    pub fn run_on(&mut self, h: &Handle, clock: SharedClock, me: Weak<RefCell<HookStorage>>) {
+        self.tasks = Some(Tasks::new(h));
+        self.me = me.clone();
+        self.clock = clock;
+
+        match Interval::new(Duration::from_secs(EXPIRE_EVERY), h) {
+            Ok(every) => h.spawn(every
+                .map_err(|e| warn!("hook expiry timer failed: {}", e))
+                .for_each(move |_| match me.upgrade() {
+                    Some(hooks) => {
+                        if let Ok(mut hooks) = hooks.try_borrow_mut() {
+                            let now = hooks.clock.now();
+                            hooks.expire(now);
+                        }
+                        Ok(())
+                    },
+                    // The connection's gone, so stop.
+                    None => Err(())
+                })),
+            Err(e) => warn!("can't start the hook expiry timer: {}", e),
+        }
+    }
+
+    /// Drops every hook whose `Meta::until` has passed.
+    pub fn expire(&mut self, now: Instant) {
+        let gone = self.meta.iter()
+            .filter(|&(_, m)| m.expired(now))
+            .map(|(id, _)| id.clone().drop())
+            .collect();
+        self.apply(gone);
+    }
+
+    /// Drops every hook for `room`, now that the bot isn't in it, and takes
+    /// back the messages they'd have sent there when they timed out.
+    pub fn left(&mut self, room: &ChannelName, mq: &MessageQueue) {
+        let gone = self.meta.iter()
+            .filter(|&(_, m)| m.room.as_ref() == Some(room))
+            .map(|(id, m)| {
+                if let Some(i) = m.late {
+                    mq.unschedule(i);
                 }
-            }
-            false
+                id.clone().drop()
+            })
+            .collect();
+        self.apply(gone);
+    }
+
+    fn add_command(&mut self, u: M, spec: Spec, cb: Command) -> M {
+        let syntax = match spec.args.map(Syntax::parse) {
+            Some(Ok(syntax)) => Some(syntax),
+            Some(Err(e)) => {
+                warn!("not adding !{}: it declares bad arguments: {}", spec.name, e);
+                return u
+            },
+            None => None
+        };
+        if let Err(taken) = self.index.0.borrow_mut().router.add(spec.name, spec.aliases, u) {
+            warn!("not adding !{}: !{} is already taken", spec.name, taken);
+            return u
         }
 
-        self.msg.insert(u, box move |ev|
-            if matches(&ev, s) {
-                cb(ev)
-            } else {
-                vec![]
+        self.index.0.borrow_mut().specs.insert(u, spec);
+        self.meta.insert(HookId::Message(u), Meta { priority: spec.priority, ..Meta::default() });
+        self.commands.insert(u, box move |ev| {
+            if config::is_disabled(&ev, spec.name)? {
+                return Ok(vec![])
+            }
+            spec.admits(&ev)?;
+            if let Some(ref syntax) = syntax {
+                let args = syntax.apply(ev.content()).map_err(|e|
+                    CommandError::Usage(format!("{} Usage: !{} {}", e, spec.name, syntax)))?;
+                ev.set_args(args);
             }
-        );
+            cb(ev)
+        });
         u
     }
 
-    pub fn join_iter<'a>(&'a self) -> Values<'a, J, Command> {
-        self.join.values()
+    fn add_route(&mut self, m: M, name: &str) {
+        if !self.commands.contains_key(&m) {
+            warn!("not routing !{}: its command is gone", name);
+        } else if let Err(taken) = self.index.0.borrow_mut().router.add(name, &[], m) {
+            warn!("not routing !{}: it's already taken", taken);
+        }
     }
 
-    pub fn msg_iter<'a>(&'a self) -> Values<'a, M, Command> {
-        self.msg.values()
+    fn drop_message(&mut self, m: M) {
+        self.msg.remove(&m);
+        self.commands.remove(&m);
+        {
+            let mut index = self.index.0.borrow_mut();
+            index.specs.remove(&m);
+            index.router.remove(&m);
+        }
+        self.forget(&HookId::Message(m));
+    }
+
+    /// Drops what's kept about a hook besides the hook itself.
+    fn forget(&mut self, id: &HookId) {
+        self.panics.remove(id);
+        self.meta.remove(id);
+    }
+
+    /// The command a message invokes, if any.
+    fn command_for(&self, ev: &Event) -> Option<(HookId, &Command)> {
+        // Never answer ourselves, or echo would never stop.
+        if ev.sender == NAME.as_str() {
+            return None
+        }
+        ev.invocation()
+            .and_then(|(cmd, _)| self.index.route(cmd))
+            .and_then(|m| self.commands.get(&m).map(|c| (HookId::Message(m), c)))
+    }
+
+    /// Every hook `ev` should be handed to, in the order they run.
+    fn hooks_for(&self, ev: &Event) -> Vec<(HookId, &Command)> {
+        let mut found: Vec<(HookId, Stage, &Command)> = match ev.ty {
+            EType::Join => self.join.iter().map(|(j, c)| (HookId::Join(*j), Stage::Typed, c)).collect(),
+            EType::Part => self.part.iter().map(|(p, c)| (HookId::Part(*p), Stage::Typed, c)).collect(),
+            EType::Message | EType::Action => {
+                let mut v = self.msg.iter()
+                    .map(|(m, c)| (HookId::Message(*m), Stage::Typed, c))
+                    .collect::<Vec<_>>();
+                v.extend(self.command_for(ev).map(|(id, c)| (id, Stage::Command, c)));
+                v
+            },
+            _ => vec![]
+        };
+        found.extend(self.event.iter().map(|(v, c)| (HookId::Event(*v), Stage::Every, c)));
+
+        let mut ranked = found.into_iter()
+            .map(|(id, stage, c)| (self.meta.get(&id).map(|m| m.priority).unwrap_or(NORMAL), stage, id, c))
+            .collect();
+        in_order(&mut ranked);
+        ranked.into_iter().map(|(_, _, id, c)| (id, c)).collect()
+    }
+
+    /// Hands `ev` to every hook that wants it and applies what they return,
+    /// until one returns `Hook::Consume`. Hooks that have expired are
+    /// dropped first, and ones that have used up their runs after.
+    /// Errors are told to the sender and counted in `metrics`. A hook that
+    /// panics says sorry and leaves the others to run; one that panics
+    /// `MAX_PANICS` times is dropped until it's added again.
+    pub fn dispatch(&mut self, ev: &Event) {
+        self.expire(ev.now());
+
+        let mut updates = vec![];
+        let mut broken = vec![];
+        let mut ran = vec![];
+        let mut fine = vec![];
+        for (id, cmd) in self.hooks_for(ev) {
+            let spec = self.spec_of(&id);
+            let name = spec.map(|s| s.name).unwrap_or("hook");
+            match panic::catch_unwind(AssertUnwindSafe(|| cmd(ev))) {
+                Ok(Ok(hooks)) => {
+                    fine.push(id.clone());
+                    let mut pending = false;
+                    let mut consumed = false;
+                    let mut ignored = false;
+                    for h in hooks.into_iter() {
+                        match h {
+                            Hook::Later(f) => {
+                                pending = true;
+                                self.later(Some(ev), name, spec.and_then(|s| s.timeout), f);
+                            },
+                            Hook::Consume => consumed = true,
+                            Hook::Ignored => ignored = true,
+                            h => updates.push(h),
+                        }
+                    }
+                    if !ignored {
+                        ran.push(id);
+                    }
+                    // A later counts when it finishes.
+                    if !pending {
+                        metrics::record(name, "ok");
+                    }
+                    if consumed {
+                        break
+                    }
+                },
+                Ok(Err(err)) => report(Some(ev), name, &err),
+                Err(payload) => {
+                    metrics::record(name, "panic");
+                    error!("hook {:?} ({}) panicked: {}", id, name, panic_message(&*payload));
+                    ev.respond_highlight(CommandError::Internal(String::new()).to_string());
+                    broken.push(id);
+                }
+            }
+        }
+        // Only panics in a row count against a hook.
+        for id in fine.into_iter() {
+            self.panics.remove(&id);
+        }
+        for id in ran.into_iter() {
+            if self.meta.get_mut(&id).map(Meta::spend).unwrap_or(false) {
+                updates.push(id.drop());
+            }
+        }
+        for id in broken.into_iter() {
+            let n = {
+                let n = self.panics.entry(id.clone()).or_insert(0);
+                *n += 1;
+                *n
+            };
+            if n >= MAX_PANICS {
+                match self.spec_of(&id) {
+                    Some(s) => {
+                        warn!("dropping !{} after {} panics", s.name, n);
+                        ev.respond(format!("!{} keeps breaking, so I've turned it off.", s.name));
+                    },
+                    None => warn!("dropping hook {:?} after {} panics", id, n),
+                }
+                updates.push(id.drop());
+            }
+        }
+        self.apply(updates);
+    }
+
+    fn spec_of(&self, id: &HookId) -> Option<Spec> {
+        match *id {
+            HookId::Message(ref m) => self.index.spec(m),
+            _ => None
+        }
+    }
+
+    /// Runs `f` on the reactor, for up to `timeout` or `default_timeout()`,
+    /// then applies the hooks it returns. If it fails, `ev`'s sender is
+    /// told, the same as if the command had failed straight away.
+    fn later(&self, ev: Option<&Event>, name: &'static str, timeout: Option<Duration>, f: Pending) {
+        let tasks = match self.tasks {
+            Some(ref t) => t,
+            None => {
+                warn!("not running later work for {}: there's no reactor yet", name);
+                return
+            }
+        };
+        let me = self.me.clone();
+        let ev = ev.cloned();
+        tasks.spawn(f, timeout.unwrap_or_else(default_timeout), move |r| {
+            let err = match r {
+                Ok(hooks) => {
+                    metrics::record(name, "ok");
+                    match me.upgrade() {
+                        Some(h) => match h.try_borrow_mut() {
+                            Ok(mut h) => h.apply(hooks),
+                            Err(_) => warn!("dropping what {} returned: hooks are busy", name),
+                        },
+                        None => debug!("dropping what {} returned: hooks are gone", name),
+                    }
+                    return
+                },
+                Err(Ended::Failed(err)) => err,
+                Err(Ended::TimedOut) => CommandError::TimedOut,
+            };
+            report(ev.as_ref(), name, &err);
+        });
     }
 
     pub fn apply(&mut self, updates: Hooks) {
         for up in updates.into_iter() {
             match up {
-                Hook::AddMessage(m,c) => {self.msg.insert(m,c);},
+                Hook::AddMessage(m,meta,c) => {
+                    self.meta.insert(HookId::Message(m), meta);
+                    self.msg.insert(m,c);
+                },
                 Hook::AddCommand(m,s,c) => {self.add_command(m,s,c);}
-                Hook::AddJoin(j,c) => {self.join.insert(j,c);},
-                Hook::DropMessage(m) => {self.msg.remove(&m);},
-                Hook::DropJoin(j) => {self.join.remove(&j);},
+                Hook::AddRoute(m,name) => {self.add_route(m,&name);},
+                Hook::AddJoin(j,meta,c) => {
+                    self.meta.insert(HookId::Join(j), meta);
+                    self.join.insert(j,c);
+                },
+                Hook::AddPart(p,meta,c) => {
+                    self.meta.insert(HookId::Part(p), meta);
+                    self.part.insert(p,c);
+                },
+                Hook::AddEvent(v,meta,c) => {
+                    self.meta.insert(HookId::Event(v), meta);
+                    self.event.insert(v,c);
+                },
+                Hook::DropMessage(m) => {self.drop_message(m);},
+                Hook::DropRoute(name) => {self.index.0.borrow_mut().router.remove_name(&name);},
+                Hook::DropJoin(j) => {
+                    self.join.remove(&j);
+                    self.forget(&HookId::Join(j));
+                },
+                Hook::DropPart(p) => {
+                    self.part.remove(&p);
+                    self.forget(&HookId::Part(p));
+                },
+                Hook::DropEvent(v) => {
+                    self.event.remove(&v);
+                    self.forget(&HookId::Event(v));
+                },
+                Hook::Later(f) => self.later(None, "hook", None, f),
+                Hook::Consume | Hook::Ignored => {},
             }
         }
     }
 }
 
+/// Counts how a hook failed, and tells the sender why if there is one.
+pub fn report(ev: Option<&Event>, name: &str, err: &CommandError) {
+    metrics::record(name, err.kind());
+    if let CommandError::Internal(ref why) = *err {
+        error!("{} failed: {}", name, why);
+    }
+    if let Some(ev) = ev {
+        ev.respond_highlight(err.to_string());
+    }
+}
+
 static UNIQUE: AtomicUsize = ATOMIC_USIZE_INIT;
 
 macro_rules! unique {
@@ -97,14 +706,31 @@ macro_rules! unique {
 
 unique!(M); // for msg-hooks
 unique!(J); // for join-hooks
+unique!(P); // for part-hooks
+unique!(V); // for hooks on every event
 unique!(W); // for word wars
 
 pub enum Hook {
-    AddMessage(M, Command),
-    AddCommand(M, &'static str, Command),
-    AddJoin(J, Command),
+    AddMessage(M, Meta, Command),
+    AddCommand(M, Spec, Command),
+    /// Another name for a command that's already added, for commands whose
+    /// names come and go at runtime.
+    AddRoute(M, String),
+    AddJoin(J, Meta, Command),
+    AddPart(P, Meta, Command),
+    AddEvent(V, Meta, Command),
     DropMessage(M),
+    DropRoute(String),
     DropJoin(J),
+    DropPart(P),
+    DropEvent(V),
+    /// Work that finishes after the command returns; see `Hook::later`.
+    Later(Pending),
+    /// Keeps the event from the hooks that would run after this one.
+    Consume,
+    /// The event wasn't one the hook was waiting for, so the run doesn't
+    /// count towards `Hook::times`.
+    Ignored,
 }
 
 pub type Hooks = Vec<Hook>;
@@ -113,22 +739,367 @@ impl Hook {
     pub fn register<F>(s: &'static str, f: F) -> Self
         where F: FnOnce(M) -> Command {
         let m = M::next();
-        Hook::AddCommand(m, s, f(m))
+        Hook::AddCommand(m, Spec::new(s), f(m))
     }
 
     pub fn join<F>(f: F) -> Self
         where F: FnOnce(J) -> Command {
         let j = J::next();
-        Hook::AddJoin(j, f(j))
+        Hook::AddJoin(j, Meta::default(), f(j))
+    }
+
+    pub fn part<F>(f: F) -> Self
+        where F: FnOnce(P) -> Command {
+        let p = P::next();
+        Hook::AddPart(p, Meta::default(), f(p))
+    }
+
+    /// Sees every event, whatever its type, after the type-specific hooks.
+    pub fn event<F>(f: F) -> Self
+        where F: FnOnce(V) -> Command {
+        let v = V::next();
+        Hook::AddEvent(v, Meta::default(), f(v))
     }
 
     pub fn register_msg<F>(f: F) -> Self
         where F: FnOnce(M) -> Command {
         let m = M::next();
-        Hook::AddMessage(m, f(m))
+        Hook::AddMessage(m, Meta::default(), f(m))
+    }
+
+    /// Changes the `Spec` of a command added with `register`; other hooks
+    /// are left alone.
+    fn with_spec<F>(self, f: F) -> Self
+        where F: FnOnce(&mut Spec) {
+        match self {
+            Hook::AddCommand(m, mut s, c) => {
+                f(&mut s);
+                Hook::AddCommand(m, s, c)
+            },
+            h => h
+        }
+    }
+
+    /// Changes the `Meta` of a hook added with `register_msg`, `join`,
+    /// `part` or `event`.
+    fn with_meta<F>(self, f: F) -> Self
+        where F: FnOnce(&mut Meta) {
+        match self {
+            Hook::AddMessage(m, mut meta, c) => {
+                f(&mut meta);
+                Hook::AddMessage(m, meta, c)
+            },
+            Hook::AddJoin(j, mut meta, c) => {
+                f(&mut meta);
+                Hook::AddJoin(j, meta, c)
+            },
+            Hook::AddPart(p, mut meta, c) => {
+                f(&mut meta);
+                Hook::AddPart(p, meta, c)
+            },
+            Hook::AddEvent(v, mut meta, c) => {
+                f(&mut meta);
+                Hook::AddEvent(v, meta, c)
+            },
+            h => h
+        }
+    }
+
+    /// Sets when a hook runs among the others for the same event; see
+    /// `Priority`.
+    pub fn priority(self, p: Priority) -> Self {
+        self.with_spec(|s| s.priority = p).with_meta(|m| m.priority = p)
+    }
+
+    /// Drops a hook after its first run; see `times`.
+    pub fn once(self) -> Self {
+        self.times(1)
+    }
+
+    /// Drops a hook after it's run `n` times, not counting runs that return
+    /// `Hook::Ignored`.
+    pub fn times(self, n: u32) -> Self {
+        self.with_meta(|m| m.runs = Some(n))
+    }
+
+    /// Drops a hook once `i` has passed, whether or not it's run.
+    pub fn until(self, i: Instant) -> Self {
+        self.with_meta(|m| m.until = Some(i))
+    }
+
+    /// Drops a hook when the bot leaves `room`.
+    pub fn while_in(self, room: ChannelName) -> Self {
+        self.with_meta(|m| m.room = Some(room))
+    }
+
+    /// Like `until`, for a hook that's queued a message for `i` to say it
+    /// timed out. The message is taken back if the hook is dropped by
+    /// `while_in` first; taking it back when the hook runs is up to the hook.
+    pub fn late(self, i: Instant) -> Self {
+        self.with_meta(|m| { m.until = Some(i); m.late = Some(i) })
+    }
+
+    /// Restricts a command to public rooms or pchats.
+    pub fn only(self, scope: Scope) -> Self {
+        self.with_spec(|s| s.scope = scope)
+    }
+
+    /// Sets who may run a command.
+    pub fn needs(self, level: Level) -> Self {
+        self.with_spec(|s| s.level = level)
+    }
+
+    /// Where `!commands` lists a command.
+    pub fn category(self, category: &'static str) -> Self {
+        self.with_spec(|s| s.category = category)
+    }
+
+    /// One sentence on what a command does.
+    pub fn summary(self, summary: &'static str) -> Self {
+        self.with_spec(|s| s.summary = summary)
+    }
+
+    /// Declares a command's arguments; see `args::Syntax`.
+    pub fn args(self, decl: &'static str) -> Self {
+        self.with_spec(|s| s.args = Some(decl))
+    }
+
+    pub fn examples(self, examples: &'static [&'static str]) -> Self {
+        self.with_spec(|s| s.examples = examples)
+    }
+
+    /// Other names a command answers to.
+    pub fn aliases(self, aliases: &'static [&'static str]) -> Self {
+        self.with_spec(|s| s.aliases = aliases)
+    }
+
+    /// How long a command's `Hook::later`s get before they're given up on.
+    pub fn timeout(self, d: Duration) -> Self {
+        self.with_spec(|s| s.timeout = Some(d))
+    }
+
+    /// Finishes a command on the reactor: `f` runs after the command returns,
+    /// and the hooks it resolves to are applied then. If it fails or takes
+    /// too long, the sender is told as if the command had returned the error.
+    pub fn later<F>(f: F) -> Self
+        where F: Future<Item = Hooks, Error = CommandError> + 'static {
+        Hook::Later(box f)
+    }
+
+    /// What this hook adds, so it can be dropped later; drops add nothing.
+    pub fn added(&self) -> Option<HookId> {
+        match *self {
+            Hook::AddMessage(m, _, _) | Hook::AddCommand(m, _, _) => Some(HookId::Message(m)),
+            Hook::AddRoute(_, ref name) => Some(HookId::Route(name.clone())),
+            Hook::AddJoin(j, _, _) => Some(HookId::Join(j)),
+            Hook::AddPart(p, _, _) => Some(HookId::Part(p)),
+            Hook::AddEvent(v, _, _) => Some(HookId::Event(v)),
+            _ => None
+        }
     }
 
     pub fn unregister(m: M) -> Self {
         Hook::DropMessage(m)
     }
 }
+
+#[test]
+fn hooks_invocation() {
+    let triggers = vec!["!".to_string(), "!!".to_string(), "participle:".to_string()];
+    assert_eq!(invocation("!kick bob bye", &triggers), Some(("kick", "bob bye")));
+    assert_eq!(invocation("participle: kick bob", &triggers), Some(("kick", "bob")));
+    assert_eq!(invocation("!!help", &triggers), Some(("help", "")));
+    assert_eq!(invocation("kick bob", &triggers), None);
+    assert_eq!(invocation("participle, kick", &triggers), None);
+}
+
+#[test]
+fn hooks_panic_message() {
+    let said = panic::catch_unwind(|| panic!("static")).unwrap_err();
+    assert_eq!(panic_message(&*said), "static");
+    let said = panic::catch_unwind(|| panic!("{} {}", "formatted", 1)).unwrap_err();
+    assert_eq!(panic_message(&*said), "formatted 1");
+    let said = panic::catch_unwind(|| panic!(42)).unwrap_err();
+    assert_eq!(panic_message(&*said), "no message");
+}
+
+#[test]
+fn hooks_command_error() {
+    let e = CommandError::Usage("Missing <i>user</i>. Usage: !kick <i>user</i>".to_string());
+    assert_eq!(e.to_string(), "Missing <i>user</i>. Usage: !kick <i>user</i>");
+    assert_eq!(e.kind(), "usage");
+    let e = CommandError::Internal("database: disk I/O error".to_string());
+    assert_eq!(e.to_string(), "Sorry, something went wrong there.");
+    assert_eq!(e.kind(), "internal");
+    assert_eq!(CommandError::TimedOut.kind(), "timeout");
+}
+
+#[test]
+fn hooks_in_order() {
+    let mut found = vec![
+        (NORMAL, Stage::Every, HookId::Event(V(1)), "every"),
+        (NORMAL, Stage::Command, HookId::Message(M(2)), "command"),
+        (NORMAL, Stage::Typed, HookId::Message(M(5)), "newer"),
+        (NORMAL, Stage::Typed, HookId::Message(M(3)), "older"),
+        (FILTER, Stage::Every, HookId::Event(V(4)), "filter"),
+    ];
+    in_order(&mut found);
+    assert_eq!(found.into_iter().map(|(_, _, _, n)| n).collect::<Vec<_>>(),
+               vec!["filter", "older", "newer", "command", "every"]);
+}
+
+#[test]
+fn hooks_meta_lifetime() {
+    let now = Instant::now();
+    let mut m = Meta { runs: Some(2), until: Some(now + Duration::from_secs(5)), ..Meta::default() };
+    assert!(!m.expired(now));
+    assert!(m.expired(now + Duration::from_secs(5)));
+    assert!(!m.spend());
+    assert!(m.spend());
+
+    let mut forever = Meta::default();
+    assert!(!forever.expired(now + Duration::from_secs(86400)));
+    assert!(!forever.spend());
+}
+
+/// A hook that counts its runs in `n`.
+#[cfg(test)]
+pub fn counting_hook(n: &Rc<::std::cell::Cell<usize>>) -> Command {
+    let n = n.clone();
+    box move |_| {
+        n.set(n.get() + 1);
+        Ok(vec![])
+    }
+}
+
+#[test]
+fn hooks_dispatch_panics() {
+    use std::cell::Cell;
+
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let ev = ::event::test_msg(&mq, "hi");
+    let broken = Rc::new(Cell::new(0));
+    let flaky = Rc::new(Cell::new(0));
+    let fine = Rc::new(Cell::new(0));
+    let (b, f) = (broken.clone(), flaky.clone());
+    let mut hooks = HookStorage::new();
+    hooks.apply(vec![
+        Hook::register_msg(|_| box move |_: &Event| -> Outcome {
+            b.set(b.get() + 1);
+            panic!("always")
+        }).priority(FILTER),
+        // Panics twice, then works once, so it never gets to MAX_PANICS.
+        Hook::register_msg(|_| box move |_: &Event| -> Outcome {
+            f.set(f.get() + 1);
+            if f.get() % 3 != 0 {
+                panic!("sometimes")
+            }
+            Ok(vec![])
+        }),
+        Hook::register_msg(|_| counting_hook(&fine)),
+    ]);
+    for _ in 0..9 {
+        hooks.dispatch(&ev);
+    }
+    assert_eq!(broken.get(), MAX_PANICS as usize);
+    assert_eq!(flaky.get(), 9);
+    assert_eq!(fine.get(), 9);
+}
+
+#[test]
+fn hooks_later_end_to_end() {
+    use futures::future;
+    use std::cell::Cell;
+    use tokio_core::reactor::Timeout;
+
+    let (mut core, _clock, mq) = ::messagequeue::test_queue();
+    let hooks = Rc::new(RefCell::new(HookStorage::new()));
+    hooks.borrow_mut().run_on(&core.handle(), mq.clock(), Rc::downgrade(&hooks));
+    let heard = Rc::new(Cell::new(0));
+    let h = heard.clone();
+    hooks.borrow_mut().apply(vec![
+        // Adds a hook, but only once it's finished.
+        Hook::register("latertest", |_| box move |_| {
+            Ok(vec![Hook::later(future::ok::<_, CommandError>(vec![Hook::register_msg(|_| counting_hook(&h))]))])
+        }),
+        Hook::register("stucktest", |_| box |_| Ok(vec![Hook::later(future::empty::<Hooks, CommandError>())]))
+            .timeout(Duration::from_millis(10)),
+    ]);
+
+    let chat = ::event::test_msg(&mq, "hi");
+    hooks.borrow_mut().dispatch(&::event::test_msg(&mq, "!latertest"));
+    hooks.borrow_mut().dispatch(&::event::test_msg(&mq, "!stucktest"));
+    hooks.borrow_mut().dispatch(&chat);
+    assert_eq!(heard.get(), 0);
+
+    core.run(Timeout::new(Duration::from_millis(50), &core.handle()).unwrap()).unwrap();
+    hooks.borrow_mut().dispatch(&chat);
+    assert_eq!(heard.get(), 1);
+
+    let counts = metrics::snapshot();
+    assert!(counts.contains(&("latertest".to_string(), "ok", 1)));
+    assert!(counts.contains(&("stucktest".to_string(), "timeout", 1)));
+    assert!(!counts.iter().any(|&(ref h, o, _)| h == "stucktest" && o == "ok"));
+    let said = mq.jobs().into_iter()
+        .map(|(_, _, job)| String::from_utf8_lossy(&job.message.as_bytes()).into_owned())
+        .collect::<Vec<_>>();
+    assert!(said.iter().any(|s| s.contains("alice: That took too long, so I gave up.")));
+}
+
+#[test]
+fn hooks_dispatch_lifetimes() {
+    use std::cell::Cell;
+
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let ev = ::event::test_msg(&mq, "!lifetimetest");
+    let now = ev.now();
+    let twice = Rc::new(Cell::new(0));
+    let brief = Rc::new(Cell::new(0));
+    let command = Rc::new(Cell::new(0));
+    let mut hooks = HookStorage::new();
+    hooks.apply(vec![
+        Hook::register_msg(|_| counting_hook(&twice)).times(2),
+        Hook::register_msg(|_| counting_hook(&brief)).until(now + Duration::from_secs(10)),
+        Hook::register("lifetimetest", |_| counting_hook(&command)),
+    ]);
+    for _ in 0..3 {
+        hooks.dispatch(&ev);
+    }
+    assert_eq!(twice.get(), 2);
+    assert_eq!(brief.get(), 3);
+    assert_eq!(command.get(), 3);
+
+    hooks.expire(now + Duration::from_secs(10));
+    hooks.dispatch(&ev);
+    assert_eq!(brief.get(), 3);
+    // Commands last until they're dropped.
+    assert_eq!(command.get(), 4);
+}
+
+#[test]
+fn hooks_dispatch_consume() {
+    use std::cell::Cell;
+
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let filtered = Rc::new(Cell::new(0));
+    let typed = Rc::new(Cell::new(0));
+    let command = Rc::new(Cell::new(0));
+    let f = filtered.clone();
+    let mut hooks = HookStorage::new();
+    hooks.apply(vec![
+        Hook::register_msg(|_| box move |e: &Event| -> Outcome {
+            if e.message.contains("secret") {
+                f.set(f.get() + 1);
+                return Ok(vec![Hook::Consume])
+            }
+            Ok(vec![])
+        }).priority(FILTER),
+        Hook::register_msg(|_| counting_hook(&typed)),
+        Hook::register("consumetest", |_| counting_hook(&command)),
+    ]);
+
+    hooks.dispatch(&::event::test_msg(&mq, "!consumetest secret"));
+    assert_eq!((filtered.get(), typed.get(), command.get()), (1, 0, 0));
+    hooks.dispatch(&::event::test_msg(&mq, "!consumetest"));
+    assert_eq!((filtered.get(), typed.get(), command.get()), (1, 1, 1));
+}
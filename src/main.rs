@@ -3,6 +3,7 @@
 #![feature(const_fn)]
 
 extern crate ansi_term;
+extern crate backtrace;
 extern crate bytes;
 extern crate chrono;
 extern crate damnpacket;
@@ -10,11 +11,13 @@ extern crate damnpacket;
 #[macro_use] extern crate diesel_codegen;
 extern crate dotenv;
 extern crate futures;
+extern crate hlua;
 #[macro_use] extern crate lazy_static;
+extern crate libc;
 #[macro_use] extern crate log;
+extern crate lua52_sys;
 extern crate env_logger;
 #[macro_use] extern crate nom;
-extern crate state;
 #[macro_use] extern crate string;
 extern crate tokio_core;
 extern crate tokio_io;
@@ -36,14 +39,27 @@ use tokio_core::reactor::{Core,Handle};
 use tokio_io::AsyncRead;
 use env_logger::LogBuilder;
 use std::env;
+use std::panic;
 
+pub mod args;
+pub mod channel;
+pub mod clock;
 pub mod codec;
 pub mod commands;
+pub mod config;
 pub mod db;
 pub mod hooks;
 pub mod event;
 pub mod handler;
 pub mod messagequeue;
+pub mod metrics;
+pub mod moderation;
+pub mod outgoing;
+pub mod permissions;
+pub mod plugin;
+pub mod router;
+pub mod script;
+pub mod tasks;
 
 use codec::DamnCodec;
 use handler::ACTIONS;
@@ -101,26 +117,46 @@ fn connect() -> SqliteConnection {
     SqliteConnection::establish(&db_url).expect(&format!("Error connecting to {}", db_url))
 }
 
+fn install_filters(mq: &MessageQueue) {
+    use outgoing::{Dedup,DryRun,Mute,Profanity};
+
+    mq.add_filter(box Mute::new());
+    // Repeats are dropped for DEDUP_SECONDS, or 5 seconds; 0 turns it off.
+    let window = env::var("DEDUP_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(5);
+    if window > 0 {
+        mq.add_filter(box Dedup::new(::std::time::Duration::from_secs(window), mq.clock()));
+    }
+    if let Ok(words) = env::var("MASKED_WORDS") {
+        mq.add_filter(box Profanity::new(words.split(',')));
+    }
+    mq.add_filter(box DryRun(env::var("DRY_RUN").is_ok()));
+}
+
 fn repeatedly(h: &Handle, addr: &SocketAddr) {
     let greeting = Message::from("dAmnClient 0.3\nagent=marsipan\n\0");
     let a2 = addr.clone();
     let h2 = h.clone();
+    let h3 = h.clone();
     let mq = MessageQueue::new(&h);
     let mq2 = mq.clone();
     let conn = ::std::rc::Rc::new(connect());
+    install_filters(&mq);
     h.spawn(TcpStream::connect(&addr, &h).then(|res|
         match res {
             Ok(stream) => Ok(stream.framed(DamnCodec).split()),
             Err(e) => Err(MarsError::from(e))
         }
     ).and_then(|(tx, rx)|
-        tx.send(greeting).and_then(|writer| {
-            let hooks = ::std::cell::RefCell::new(hooks::HookStorage::new());
-            hooks.borrow_mut().apply(commands::default_cmds());
+        tx.send(greeting).and_then(move |writer| {
+            let hooks = ::std::rc::Rc::new(::std::cell::RefCell::new(hooks::HookStorage::new()
+                .case_sensitive(env::var("CASE_SENSITIVE_COMMANDS").is_ok())));
+            let channels = channel::Channels::new();
+            hooks.borrow_mut().run_on(&h3, mq.clock(), ::std::rc::Rc::downgrade(&hooks));
+            hooks.borrow_mut().apply(commands::default_cmds(&conn));
             rx.and_then(move |item| {
                 dump(&item, true);
                 match ACTIONS.get(&item.name[..]) {
-                    Some(f) => f(item, mq.clone(), &mut hooks.borrow_mut(), &conn),
+                    Some(f) => f(item, mq.clone(), &mut hooks.borrow_mut(), &conn, &channels),
                     _ => debug!("unknown message")
                 };
                 Ok(None)
@@ -161,8 +197,18 @@ fn log_init() -> Result<(), log::SetLoggerError> {
     ).init()
 }
 
+/// Panics in hooks are caught, so they'd otherwise only show up on stderr;
+/// this puts them, and where they came from, in the log.
+fn panic_init() {
+    panic::set_hook(box |info| {
+        let at = info.location().map(|l| format!("{}:{}", l.file(), l.line())).unwrap_or_default();
+        error!("panicked at {}: {}\n{:?}", at, hooks::panic_message(info.payload()), backtrace::Backtrace::new());
+    });
+}
+
 fn main() {
     log_init().unwrap();
+    panic_init();
     let mut core = Core::new().unwrap();
     let handle = core.handle();
     let addr = "chat.deviantart.com:3900".to_socket_addrs().unwrap().next().unwrap();
@@ -1,22 +1,103 @@
+use chrono::{DateTime,Local};
 use futures::Async;
 use futures::Future;
 use futures::Stream;
 use futures::task;
+use futures::task::Task;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap,HashMap,VecDeque};
 use std::rc::Rc;
 use std::time::Duration;
 use std::time::Instant;
 use tokio_core::reactor::Handle;
 use tokio_core::reactor::Timeout;
 
-use damnpacket::Message;
+use clock::{self,SharedClock};
+use damnpacket::{ChannelName,Message,Username};
+use outgoing::{Filter,Pipeline};
+use std::any::Any;
 use futures;
 use MarsError;
 
+/// A message waiting in the queue, plus what we know about who asked for it.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub message: Message,
+    pub owner: Option<Username>,
+    pub channel: Option<ChannelName>,
+    pub description: String,
+    pub every: Option<Duration>,
+    /// Report it if the server says it failed.
+    pub watch: bool,
+}
+
+impl Job {
+    pub fn new(message: Message) -> Self {
+        Job {
+            message: message,
+            owner: None,
+            channel: None,
+            description: String::new(),
+            every: None,
+            watch: false,
+        }
+    }
+
+    pub fn owner(mut self, owner: Username) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn channel(mut self, channel: ChannelName) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn description<S>(mut self, description: S) -> Self
+        where S: Into<String> {
+        self.description = description.into();
+        self
+    }
+
+    pub fn every(mut self, d: Duration) -> Self {
+        self.every = Some(d);
+        self
+    }
+
+    pub fn watch(mut self) -> Self {
+        self.watch = true;
+        self
+    }
+}
+
+#[derive(PartialEq,Eq,Hash,Clone,Copy,Debug)]
+pub struct JobId(usize);
+
+impl ::std::fmt::Display for JobId {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ::std::str::FromStr for JobId {
+    type Err = ::std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(JobId)
+    }
+}
+
 struct MQ {
-    heap: FakeHeap<Instant, Message>,
+    heap: FakeHeap<Instant, (JobId, Job)>,
+    index: HashMap<JobId, Instant>,
+    next_id: usize,
+    filters: Pipeline,
+    /// Recently sent packets that the server only answers when they fail,
+    /// oldest first.
+    sent: VecDeque<(Instant, Job)>,
     timeout: Option<Timeout>,
+    task: Option<Task>,
+    clock: SharedClock,
     handle: Handle
 }
 
@@ -33,6 +114,10 @@ impl<K,V> FakeHeap<K,V> where K: Ord {
         self._map.iter().next()
     }
 
+    fn contains(&self, k: &K) -> bool {
+        self._map.contains_key(k)
+    }
+
     fn remove(&mut self, k: K) -> Option<V> {
         self._map.remove(&k)
     }
@@ -45,72 +130,172 @@ impl<K,V> FakeHeap<K,V> where K: Ord {
     }
 }
 
+/// How many sent packets `failed` looks back through.
+const MAX_SENT: usize = 256;
+
 #[derive(Clone)]
 pub struct MessageQueue(Rc<RefCell<MQ>>);
 
 impl MQ {
-    fn new(h: &Handle) -> Self {
+    fn new(h: &Handle, clock: SharedClock) -> Self {
         MQ {
             heap: FakeHeap {
                 _map: BTreeMap::new()
             },
+            index: HashMap::new(),
+            next_id: 0,
+            filters: Pipeline::new(),
+            sent: VecDeque::new(),
             timeout: None,
+            task: None,
+            clock: clock,
             handle: h.clone()
         }
     }
 
     fn push(&mut self, msg: Message) -> Instant {
-        self.schedule_at(msg, Instant::now())
+        let now = self.clock.now();
+        self.schedule_at(msg, now)
     }
 
     fn schedule(&mut self, msg: Message, d: Duration) -> Instant {
-        self.schedule_at(msg, Instant::now() + d)
+        let now = self.clock.now();
+        self.schedule_at(msg, now + d)
     }
 
     fn schedule_at(&mut self, msg: Message, ins: Instant) -> Instant {
-        self.heap.insert(ins, msg);
+        self.submit(Job::new(msg), ins).1
+    }
+
+    fn submit(&mut self, job: Job, ins: Instant) -> (JobId, Instant) {
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        let ins = self.insert(id, job, ins);
         self.reschedule();
+        (id, ins)
+    }
+
+    fn insert(&mut self, id: JobId, job: Job, mut ins: Instant) -> Instant {
+        // Instants double as message handles, so nudge collisions forward
+        // rather than overwrite whatever is already queued.
+        while self.heap.contains(&ins) {
+            ins += Duration::new(0, 1);
+        }
+        self.heap.insert(ins, (id, job));
+        self.index.insert(id, ins);
         ins
     }
 
     fn unschedule(&mut self, ins: Instant) -> Option<Message> {
-        let res = self.heap.remove(ins);
+        let res = self.heap.remove(ins).map(|(id, job)| {
+            self.index.remove(&id);
+            job.message
+        });
         self.reschedule();
         res
     }
 
+    fn cancel(&mut self, id: JobId) -> Option<Job> {
+        let res = match self.index.remove(&id) {
+            Some(ins) => self.heap.remove(ins).map(|x|x.1),
+            None => None
+        };
+        self.reschedule();
+        res
+    }
+
+    fn cancel_where<F>(&mut self, f: F) -> usize
+        where F: Fn(&Job) -> bool {
+        let doomed = self.heap._map.iter()
+            .filter(|&(_, &(_, ref job))| f(job))
+            .map(|(&ins, &(id, _))| (ins, id))
+            .collect::<Vec<_>>();
+        for &(ins, id) in doomed.iter() {
+            self.heap.remove(ins);
+            self.index.remove(&id);
+        }
+        self.reschedule();
+        doomed.len()
+    }
+
+    /// Keeps `job` for `failed` if it's a `kick`, `send` or `set`. Error
+    /// replies don't say which packet they're for, so every one is kept,
+    /// not just the watched ones, or a failed chat line would be blamed on
+    /// the ban before it.
+    fn record(&mut self, job: Job) {
+        match &job.message.name[..] {
+            b"kick" | b"send" | b"set" => {},
+            _ => return
+        }
+        let now = self.clock.now();
+        self.sent.push_back((now, job));
+        if self.sent.len() > MAX_SENT {
+            self.sent.pop_front();
+        }
+    }
+
+    fn failed(&mut self, name: &[u8], room: &ChannelName) -> Option<Job> {
+        let now = self.clock.now();
+        self.sent.retain(|&(at, _)| now.duration_since(at) < Duration::from_secs(60));
+        let found = self.sent.iter().position(|&(_, ref job)| {
+            &job.message.name[..] == name &&
+                job.message.argument.as_ref().and_then(|r| ChannelName::parse(r)).as_ref() == Some(room)
+        });
+        found.and_then(|i| self.sent.remove(i)).map(|x| x.1).and_then(|job| if job.watch {
+            Some(job)
+        } else {
+            None
+        })
+    }
+
+    fn jobs(&self) -> Vec<(JobId, Instant, Job)> {
+        self.heap._map.iter().map(|(&ins, &(id, ref job))| (id, ins, job.clone())).collect()
+    }
+
     fn reschedule(&mut self) {
         if let Some(stamp) = self.heap.peek().map(|x|x.0).cloned() {
-            let i = Instant::now();
+            let i = self.clock.now();
             self.timeout = Some(Timeout::new(if stamp < i {
                 Duration::new(0,0)
             } else {
                 stamp - i
             }, &self.handle).unwrap());
-            task::park().unpark();
+            if let Some(ref t) = self.task {
+                t.unpark();
+            }
         } else {
             self.timeout = None;
         }
     }
 
     fn poll(&mut self) -> futures::Poll<Option<Message>, MarsError> {
-        let mut removed_item = false;
-        let status = match self.timeout {
-            None => Ok(Async::NotReady),
+        self.task = Some(task::park());
+        let fired = match self.timeout {
+            None => false,
             Some(ref mut t) => match t.poll() {
-                Ok(Async::Ready(_)) => {
-                    removed_item = true;
-                    let soonest = self.heap.pop().expect("Invariant: timeout with empty heap").1;
-                    Ok(Async::Ready(Some(soonest)))
-                },
-                Ok(Async::NotReady) => Ok(Async::NotReady),
-                Err(e) => Err(MarsError::from(e))
+                Ok(Async::Ready(_)) => true,
+                Ok(Async::NotReady) => false,
+                Err(e) => return Err(MarsError::from(e))
             }
         };
-        if removed_item {
-            self.reschedule();
+        if !fired {
+            return Ok(Async::NotReady)
+        }
+        let (ins, (id, soonest)) = self.heap.pop().expect("Invariant: timeout with empty heap");
+        self.index.remove(&id);
+        let msg = soonest.message.clone();
+        let job = soonest.clone();
+        if let Some(d) = soonest.every {
+            self.insert(id, soonest, ins + d);
+        }
+        self.reschedule();
+        match self.filters.apply(msg) {
+            Some(m) => {
+                self.record(job);
+                Ok(Async::Ready(Some(m)))
+            },
+            None => self.poll()
         }
-        status
     }
 }
 
@@ -131,8 +316,70 @@ impl MessageQueue {
         self.0.borrow_mut().unschedule(ins)
     }
 
+    pub fn submit(&self, job: Job, ins: Instant) -> (JobId, Instant) {
+        self.0.borrow_mut().submit(job, ins)
+    }
+
+    pub fn cancel(&self, id: JobId) -> Option<Job> {
+        self.0.borrow_mut().cancel(id)
+    }
+
+    pub fn cancel_owner(&self, owner: &Username) -> usize {
+        self.0.borrow_mut().cancel_where(|job| job.owner.as_ref() == Some(owner))
+    }
+
+    pub fn cancel_channel(&self, channel: &ChannelName) -> usize {
+        self.0.borrow_mut().cancel_where(|job| job.channel.as_ref() == Some(channel))
+    }
+
+    /// The watched job a `name` error packet about `room` is answering: the
+    /// oldest such packet sent in the last minute, if it was watched.
+    pub fn failed(&self, name: &[u8], room: &ChannelName) -> Option<Job> {
+        self.0.borrow_mut().failed(name, room)
+    }
+
+    /// Everything still waiting to be sent, soonest first.
+    pub fn jobs(&self) -> Vec<(JobId, Instant, Job)> {
+        self.0.borrow().jobs()
+    }
+
+    pub fn add_filter(&self, f: Box<Filter>) {
+        self.0.borrow_mut().filters.push(f)
+    }
+
+    /// Runs `f` against the installed filter of type `T`, if there is one.
+    pub fn with_filter<T, R, F>(&self, f: F) -> Option<R>
+        where T: Any,
+              F: FnOnce(&mut T) -> R {
+        self.0.borrow_mut().filters.get::<T>().map(f)
+    }
+
+    pub fn next_at(&self) -> Option<Instant> {
+        self.0.borrow().heap.peek().map(|x|x.0).cloned()
+    }
+
+    pub fn now(&self) -> Instant {
+        self.0.borrow().clock.now()
+    }
+
+    pub fn local_now(&self) -> DateTime<Local> {
+        self.0.borrow().clock.local()
+    }
+
+    pub fn clock(&self) -> SharedClock {
+        self.0.borrow().clock.clone()
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.0.borrow().handle.clone()
+    }
+
     pub fn new(h: &Handle) -> Self {
-        MessageQueue(Rc::new(RefCell::new(MQ::new(h))))
+        MessageQueue::with_clock(h, clock::system())
+    }
+
+    pub fn with_clock(h: &Handle, clock: SharedClock) -> Self {
+        MessageQueue(Rc::new(RefCell::new(MQ::new(h, clock))))
     }
 }
 
@@ -144,3 +391,99 @@ impl Stream for MessageQueue {
         self.0.borrow_mut().poll()
     }
 }
+
+#[cfg(test)]
+pub fn test_queue() -> (::tokio_core::reactor::Core, Rc<::clock::ManualClock>, MessageQueue) {
+    use chrono::TimeZone;
+
+    let core = ::tokio_core::reactor::Core::new().unwrap();
+    let clock = Rc::new(::clock::ManualClock::new(Local.ymd(2017, 5, 1).and_hms(12, 0, 0)));
+    let mq = MessageQueue::with_clock(&core.handle(), clock.clone());
+    (core, clock, mq)
+}
+
+#[test]
+fn schedule_uses_clock() {
+    use clock::Clock;
+
+    let (_core, clock, mq) = test_queue();
+    let start = clock.now();
+    clock.advance(Duration::from_secs(5));
+    let at = mq.schedule(Message::from("pong\n\0"), Duration::from_secs(10));
+    assert_eq!(at - start, Duration::from_secs(15));
+    assert_eq!(mq.next_at(), Some(at));
+}
+
+#[test]
+fn simultaneous_pushes_are_kept() {
+    let (_core, _clock, mq) = test_queue();
+    let a = mq.push(Message::from("pong\n\0"));
+    let b = mq.push(Message::from("pong\n\0"));
+    assert!(a < b);
+    assert_eq!(mq.unschedule(a), Some(Message::from("pong\n\0")));
+    assert_eq!(mq.next_at(), Some(b));
+}
+
+#[test]
+fn cancel_by_owner_and_channel() {
+    let (_core, _clock, mq) = test_queue();
+    let d = Duration::from_secs(60);
+    let now = mq.now();
+    let job = |owner: &str, room: &str| Job::new(Message::from("pong\n\0"))
+        .owner(Username::from(owner))
+        .channel(ChannelName::Chat(room.to_string()));
+    mq.submit(job("alice", "a"), now + d);
+    mq.submit(job("bob", "a"), now + d);
+    let kept = mq.submit(job("bob", "b"), now + d).0;
+
+    assert_eq!(mq.cancel_owner(&Username::from("Alice")), 1);
+    assert_eq!(mq.jobs().len(), 2);
+    assert_eq!(mq.cancel_channel(&ChannelName::Chat("A".to_string())), 1);
+    assert_eq!(mq.jobs().into_iter().map(|x|x.0).collect::<Vec<_>>(), vec![kept]);
+    assert!(mq.cancel(kept).is_some());
+    assert!(mq.cancel(kept).is_none());
+    assert_eq!(mq.next_at(), None);
+}
+
+#[test]
+fn failed_matches_watched_jobs() {
+    let (_core, _clock, mq) = test_queue();
+    let room = ChannelName::Chat("room".to_string());
+    let kick = Job::new(Message::from("kick chat:room\nu=bob\n\0"))
+        .channel(room.clone())
+        .description("kick bob")
+        .watch();
+    mq.0.borrow_mut().record(kick);
+
+    assert!(mq.failed(b"send", &room).is_none());
+    assert!(mq.failed(b"kick", &ChannelName::Chat("other".to_string())).is_none());
+    assert_eq!(mq.failed(b"kick", &room).map(|j| j.description), Some("kick bob".to_string()));
+    assert!(mq.failed(b"kick", &room).is_none());
+}
+
+#[test]
+fn failed_blames_the_oldest_send() {
+    let (_core, _clock, mq) = test_queue();
+    let room = ChannelName::Chat("room".to_string());
+    let ban = Job::new(Message::from("send chat:room\n\nban bob\n\0"))
+        .description("ban bob")
+        .watch();
+    mq.0.borrow_mut().record(Job::new(Message::from("send chat:room\n\nmsg main\n\nhi\0")));
+    mq.0.borrow_mut().record(ban);
+    mq.0.borrow_mut().record(Job::new(Message::from("join chat:room\n\0")));
+
+    // The chat line went first, so the first error is its.
+    assert!(mq.failed(b"send", &room).is_none());
+    assert_eq!(mq.failed(b"send", &room).map(|j| j.description), Some("ban bob".to_string()));
+    assert!(mq.failed(b"send", &room).is_none());
+}
+
+#[test]
+fn failed_forgets_old_jobs() {
+    let (_core, clock, mq) = test_queue();
+    let room = ChannelName::Chat("room".to_string());
+    let set = Job::new(Message::from("set chat:room\np=topic\n\0")).channel(room.clone()).watch();
+    mq.0.borrow_mut().record(set);
+    clock.advance(Duration::from_secs(61));
+    assert!(mq.failed(b"set", &room).is_none());
+}
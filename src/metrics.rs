@@ -0,0 +1,26 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref COUNTS: Mutex<BTreeMap<(String, &'static str), u64>> = Mutex::new(BTreeMap::new());
+}
+
+/// Counts one run of `hook` that ended in `outcome`: `ok`, or the kind of
+/// `CommandError` it returned, or `panic`.
+pub fn record(hook: &str, outcome: &'static str) {
+    *COUNTS.lock().unwrap().entry((hook.to_string(), outcome)).or_insert(0) += 1;
+}
+
+/// Every count so far, sorted by hook and then outcome.
+pub fn snapshot() -> Vec<(String, &'static str, u64)> {
+    COUNTS.lock().unwrap().iter().map(|(&(ref h, o), &n)| (h.clone(), o, n)).collect()
+}
+
+#[test]
+fn metrics_count() {
+    record("metrics-test", "ok");
+    record("metrics-test", "usage");
+    record("metrics-test", "ok");
+    let mine = snapshot().into_iter().filter(|&(ref h, _, _)| h == "metrics-test").collect::<Vec<_>>();
+    assert_eq!(mine, vec![("metrics-test".to_string(), "ok", 2), ("metrics-test".to_string(), "usage", 1)]);
+}
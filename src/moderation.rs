@@ -0,0 +1,76 @@
+use damnpacket::{ChannelName,Message,MessageBody,Username};
+use std::collections::HashMap;
+
+fn packet(name: &[u8], room: &ChannelName, attrs: Vec<(&[u8], String)>, body: Option<String>) -> Message {
+    Message {
+        name: name.to_vec(),
+        argument: Some(room.as_bytes()),
+        attrs: attrs.into_iter().map(|(k, v)| (k.to_vec(), v)).collect::<HashMap<_, _>>(),
+        body: body.map(MessageBody::from),
+    }
+}
+
+/// `send`s a subpacket like `promote user`, with an optional body.
+fn send(room: &ChannelName, what: &str, user: &Username, body: Option<&str>) -> Message {
+    packet(b"send", room, vec![], Some(match body {
+        Some(b) => format!("{} {}\n\n{}\0", what, user, b),
+        None => format!("{} {}\n\0", what, user),
+    }))
+}
+
+pub fn kick(room: &ChannelName, user: &Username, reason: Option<&str>) -> Message {
+    packet(b"kick", room, vec![(b"u", user.to_string())],
+        reason.map(|r| format!("{}\0", r)))
+}
+
+pub fn ban(room: &ChannelName, user: &Username) -> Message {
+    send(room, "ban", user, None)
+}
+
+pub fn unban(room: &ChannelName, user: &Username) -> Message {
+    send(room, "unban", user, None)
+}
+
+/// Without a privclass, the server picks the next one up.
+pub fn promote(room: &ChannelName, user: &Username, privclass: Option<&str>) -> Message {
+    send(room, "promote", user, privclass)
+}
+
+/// Without a privclass, the server picks the next one down.
+pub fn demote(room: &ChannelName, user: &Username, privclass: Option<&str>) -> Message {
+    send(room, "demote", user, privclass)
+}
+
+/// `what` is `topic` or `title`.
+pub fn set(room: &ChannelName, what: &str, text: &str) -> Message {
+    packet(b"set", room, vec![(b"p", what.to_string())], Some(format!("{}\0", text)))
+}
+
+#[cfg(test)]
+fn room() -> ChannelName {
+    ChannelName::Chat("room".to_string())
+}
+
+#[test]
+fn moderation_kick() {
+    assert_eq!(kick(&room(), &"bob".into(), Some("bye")).as_bytes(),
+               b"kick chat:room\nu=bob\n\nbye\0".to_vec());
+    assert_eq!(kick(&room(), &"bob".into(), None).as_bytes(),
+               b"kick chat:room\nu=bob\n\0".to_vec());
+}
+
+#[test]
+fn moderation_send() {
+    assert_eq!(ban(&room(), &"bob".into()).as_bytes(),
+               b"send chat:room\n\nban bob\n\0".to_vec());
+    assert_eq!(promote(&room(), &"bob".into(), Some("Operators")).as_bytes(),
+               b"send chat:room\n\npromote bob\n\nOperators\0".to_vec());
+    assert_eq!(demote(&room(), &"bob".into(), None).as_bytes(),
+               b"send chat:room\n\ndemote bob\n\0".to_vec());
+}
+
+#[test]
+fn moderation_set() {
+    assert_eq!(set(&room(), "topic", "be nice").as_bytes(),
+               b"set chat:room\np=topic\n\nbe nice\0".to_vec());
+}
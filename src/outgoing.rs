@@ -0,0 +1,271 @@
+use clock::SharedClock;
+use damnpacket::{ChannelName,Message,MessageBody};
+use std::any::Any;
+use std::collections::{HashMap,HashSet};
+use std::time::{Duration,Instant};
+
+/// A step between `MessageQueue` and the socket. Returning `None` drops the
+/// message; anything else is handed to the next filter.
+pub trait Filter {
+    fn apply(&mut self, msg: Message) -> Option<Message>;
+
+    fn as_any(&mut self) -> &mut Any;
+}
+
+pub struct Pipeline(Vec<Box<Filter>>);
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline(vec![])
+    }
+
+    pub fn push(&mut self, f: Box<Filter>) {
+        self.0.push(f)
+    }
+
+    pub fn apply(&mut self, msg: Message) -> Option<Message> {
+        self.0.iter_mut().fold(Some(msg), |m, f| m.and_then(|m| f.apply(m)))
+    }
+
+    /// The first filter of type `T`, if one is installed.
+    pub fn get<T>(&mut self) -> Option<&mut T>
+        where T: Any {
+        self.0.iter_mut().filter_map(|f| f.as_any().downcast_mut::<T>()).next()
+    }
+}
+
+/// Splits a `send` packet into its room and the text after the inner header.
+fn chat_text(msg: &Message) -> Option<(&[u8], &[u8])> {
+    if msg.name != b"send" {
+        return None
+    }
+    let room = match msg.argument {
+        Some(ref r) => &r[..],
+        None => return None
+    };
+    let body = match msg.body {
+        Some(ref b) => b.as_bytes(),
+        None => return None
+    };
+    body.windows(2).position(|w| w == b"\n\n").map(|i| {
+        let text = &body[i + 2..];
+        (room, if text.last() == Some(&0) { &text[..text.len() - 1] } else { text })
+    })
+}
+
+fn with_text(mut msg: Message, text: Vec<u8>) -> Message {
+    let mut bytes = {
+        let body = msg.body.as_ref().map(|b| b.as_bytes()).unwrap_or(&[]);
+        let i = body.windows(2).position(|w| w == b"\n\n").unwrap_or(body.len());
+        body[..i].to_vec()
+    };
+    bytes.extend(b"\n\n");
+    bytes.extend(text);
+    bytes.push(0);
+    msg.body = Some(MessageBody::from_raw(bytes));
+    msg
+}
+
+/// Drops everything said in a muted room, however its name is cased.
+pub struct Mute(HashSet<ChannelName>);
+
+impl Mute {
+    pub fn new() -> Self {
+        Mute(HashSet::new())
+    }
+
+    pub fn mute(&mut self, room: &ChannelName) -> bool {
+        self.0.insert(room.clone())
+    }
+
+    pub fn unmute(&mut self, room: &ChannelName) -> bool {
+        self.0.remove(room)
+    }
+}
+
+impl Filter for Mute {
+    fn apply(&mut self, msg: Message) -> Option<Message> {
+        let muted = chat_text(&msg)
+            .and_then(|(room, _)| ChannelName::parse(room))
+            .map(|room| self.0.contains(&room))
+            .unwrap_or(false);
+        if muted {
+            debug!("muted: {:?}", msg);
+            return None
+        }
+        Some(msg)
+    }
+
+    fn as_any(&mut self) -> &mut Any { self }
+}
+
+/// Drops a line if it's identical to the last one sent to the same room,
+/// and that was less than `window` ago.
+pub struct Dedup {
+    last: HashMap<Vec<u8>, (Vec<u8>, Instant)>,
+    window: Duration,
+    clock: SharedClock,
+}
+
+impl Dedup {
+    pub fn new(window: Duration, clock: SharedClock) -> Self {
+        Dedup {
+            last: HashMap::new(),
+            window: window,
+            clock: clock,
+        }
+    }
+}
+
+impl Filter for Dedup {
+    fn apply(&mut self, msg: Message) -> Option<Message> {
+        let now = self.clock.now();
+        let dup = match chat_text(&msg) {
+            Some((room, text)) => {
+                let dup = self.last.get(room)
+                    .map(|&(ref last, at)| &last[..] == text && now - at < self.window)
+                    .unwrap_or(false);
+                if !dup {
+                    self.last.insert(room.to_vec(), (text.to_vec(), now));
+                }
+                dup
+            },
+            None => false
+        };
+        if dup {
+            debug!("duplicate: {:?}", msg);
+            return None
+        }
+        Some(msg)
+    }
+
+    fn as_any(&mut self) -> &mut Any { self }
+}
+
+/// Replaces any of `words` appearing as a whole word with asterisks.
+pub struct Profanity(Vec<Vec<u8>>);
+
+impl Profanity {
+    pub fn new<I, S>(words: I) -> Self
+        where I: IntoIterator<Item=S>,
+              S: AsRef<str> {
+        Profanity(words.into_iter()
+            .map(|w| w.as_ref().trim().to_lowercase().into_bytes())
+            .filter(|w| !w.is_empty())
+            .collect())
+    }
+
+    fn mask(&self, text: &[u8]) -> Option<Vec<u8>> {
+        let lower = text.to_ascii_lowercase();
+        let mut out = text.to_vec();
+        let mut changed = false;
+        let boundary = |i: usize| i >= text.len() || !text[i].is_ascii_alphanumeric();
+        for w in self.0.iter() {
+            let mut i = 0;
+            while i + w.len() <= lower.len() {
+                if &lower[i..i + w.len()] == &w[..]
+                    && (i == 0 || boundary(i - 1)) && boundary(i + w.len()) {
+                    for b in out[i..i + w.len()].iter_mut() {
+                        *b = b'*';
+                    }
+                    changed = true;
+                    i += w.len();
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        if changed { Some(out) } else { None }
+    }
+}
+
+impl Filter for Profanity {
+    fn apply(&mut self, msg: Message) -> Option<Message> {
+        let masked = chat_text(&msg).and_then(|(_, text)| self.mask(text));
+        Some(match masked {
+            Some(text) => with_text(msg, text),
+            None => msg
+        })
+    }
+
+    fn as_any(&mut self) -> &mut Any { self }
+}
+
+/// While enabled, logs chat lines instead of sending them.
+pub struct DryRun(pub bool);
+
+impl Filter for DryRun {
+    fn apply(&mut self, msg: Message) -> Option<Message> {
+        if self.0 {
+            if let Some((room, text)) = chat_text(&msg) {
+                info!("dry run: {} <- {}", string!(room), string!(text));
+                return None
+            }
+        }
+        Some(msg)
+    }
+
+    fn as_any(&mut self) -> &mut Any { self }
+}
+
+#[cfg(test)]
+fn say(room: &str, text: &str) -> Message {
+    Message {
+        name: b"send".to_vec(),
+        argument: Some(room.as_bytes().to_vec()),
+        attrs: HashMap::new(),
+        body: Some(MessageBody::from(format!("msg main\n\n{}\0", text))),
+    }
+}
+
+#[test]
+fn pipeline_mute() {
+    let mut p = Pipeline::new();
+    p.push(box Mute::new());
+    p.get::<Mute>().unwrap().mute(&"#a".parse().unwrap());
+    assert_eq!(p.apply(say("chat:a", "hi")), None);
+    assert_eq!(p.apply(say("chat:A", "hi")), None);
+    assert_eq!(p.apply(say("chat:b", "hi")), Some(say("chat:b", "hi")));
+    assert_eq!(p.apply(Message::from("pong\n\0")), Some(Message::from("pong\n\0")));
+    assert!(p.get::<Mute>().unwrap().unmute(&"#A".parse().unwrap()));
+    assert_eq!(p.apply(say("chat:a", "hi")), Some(say("chat:a", "hi")));
+}
+
+#[test]
+fn pipeline_dedup() {
+    use chrono::{Local,TimeZone};
+    use clock::ManualClock;
+    use std::rc::Rc;
+
+    let clock = Rc::new(ManualClock::new(Local.ymd(2017, 5, 1).and_hms(12, 0, 0)));
+    let mut p = Pipeline::new();
+    p.push(box Dedup::new(Duration::from_secs(5), clock.clone()));
+    assert!(p.apply(say("chat:a", "hi")).is_some());
+    assert!(p.apply(say("chat:a", "hi")).is_none());
+    assert!(p.apply(say("chat:b", "hi")).is_some());
+    assert!(p.apply(say("chat:a", "bye")).is_some());
+    assert!(p.apply(say("chat:a", "hi")).is_some());
+
+    // The same line is fine again once the window has passed.
+    clock.advance(Duration::from_secs(4));
+    assert!(p.apply(say("chat:a", "hi")).is_none());
+    clock.advance(Duration::from_secs(1));
+    assert!(p.apply(say("chat:a", "hi")).is_some());
+}
+
+#[test]
+fn pipeline_profanity() {
+    let mut p = Pipeline::new();
+    p.push(box Profanity::new(vec!["heck"]));
+    assert_eq!(p.apply(say("chat:a", "oh Heck, heckle")), Some(say("chat:a", "oh ****, heckle")));
+}
+
+#[test]
+fn pipeline_dry_run() {
+    let mut p = Pipeline::new();
+    p.push(box DryRun(true));
+    assert_eq!(p.apply(say("chat:a", "hi")), None);
+    assert_eq!(p.apply(Message::from("pong\n\0")), Some(Message::from("pong\n\0")));
+    p.get::<DryRun>().unwrap().0 = false;
+    assert!(p.apply(say("chat:a", "hi")).is_some());
+}
@@ -0,0 +1,109 @@
+use damnpacket::{ChannelName,Username};
+use diesel::QueryResult;
+use event::Event;
+use std::fmt;
+
+/// What a command asks of whoever runs it. Every level also lets in the
+/// ones above it, and the bot owner passes them all.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Anyone,
+    /// At least this privclass order in the room the command is used in.
+    Order(u32),
+    /// Granted `admin` in the room, or everywhere.
+    Admin,
+    Owner,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Level::Anyone => write!(f, "anyone"),
+            Level::Order(n) => write!(f, "members of privclass order {} or higher", n),
+            Level::Admin => write!(f, "admins of this room"),
+            Level::Owner => write!(f, "my owner"),
+        }
+    }
+}
+
+/// The grant name that makes someone an admin, rather than letting them run
+/// one command.
+pub static ADMIN: &'static str = "admin";
+
+/// The channel stored for grants that hold in every room.
+pub static EVERYWHERE: &'static str = "*";
+
+pub mod models {
+    use ::db::grants;
+
+    #[derive(Queryable,Debug)]
+    pub struct Grant {
+        id: i32,
+        pub user: String,
+        pub channel: String,
+        pub name: String,
+    }
+
+    #[derive(Insertable,Debug)]
+    #[table_name="grants"]
+    pub struct NewGrant {
+        pub user: String,
+        pub channel: String,
+        pub name: String,
+    }
+
+    impl Grant {
+        pub fn of(u: &str) -> ::diesel::helper_types::FindBy<
+                ::db::grants::dsl::grants,
+                ::db::grants::dsl::user,
+                String> {
+            use ::db::grants::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            grants.filter(user.eq(u.to_string()))
+        }
+    }
+}
+
+/// Usernames and rooms are stored folded, since dAmn doesn't care about case.
+pub fn user_key(u: &Username) -> String {
+    u.as_str().to_lowercase()
+}
+
+pub fn room_key(c: &ChannelName) -> String {
+    c.to_string().to_lowercase()
+}
+
+/// Names the sender has been granted in the current room.
+fn granted(e: &Event) -> QueryResult<Vec<String>> {
+    use self::models::Grant;
+
+    let room = room_key(&e.chatroom);
+    Ok(e.load(Grant::of(&user_key(&e.sender)))?.into_iter()
+        .filter(|g: &Grant| g.channel == room || g.channel == EVERYWHERE)
+        .map(|g| g.name)
+        .collect())
+}
+
+/// Whether the sender of `e` may run `command`, which asks for `level`. An
+/// explicit grant of the command itself lets someone in whatever its level.
+pub fn allowed(e: &Event, level: Level, command: &str) -> QueryResult<bool> {
+    if level == Level::Anyone || e.is_owner() {
+        return Ok(true)
+    }
+
+    let grants = granted(e)?;
+    if grants.iter().any(|g| g == command) {
+        return Ok(true)
+    }
+    let admin = grants.iter().any(|g| g == ADMIN);
+
+    Ok(match level {
+        Level::Anyone => true,
+        Level::Order(n) => admin || e.sender_order().map(|o| o >= n).unwrap_or(false),
+        Level::Admin => admin,
+        Level::Owner => false,
+    })
+}
@@ -0,0 +1,192 @@
+use diesel::sqlite::SqliteConnection;
+use hooks::{CommandError,Hook,HookId,Hooks};
+use std::env;
+use std::sync::{Arc,Mutex};
+
+/// What a plugin gets to set itself up with.
+pub struct Context<'a> {
+    pub db: &'a SqliteConnection,
+    plugin: &'static str,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(db: &'a SqliteConnection, plugin: &'static str) -> Self {
+        Context {
+            db: db,
+            plugin: plugin,
+        }
+    }
+
+    /// `<PLUGIN>_<KEY>` from the environment, like `WORDWAR_MAX_MINUTES`.
+    pub fn setting(&self, key: &str) -> Option<String> {
+        env::var(format!("{}_{}", self.plugin, key).to_uppercase()).ok()
+    }
+}
+
+/// A group of commands and hooks that come and go together. State they
+/// share belongs to the plugin, and is handed to its commands when they're
+/// made, rather than living in statics.
+pub trait Plugin: Send {
+    fn name(&self) -> &'static str;
+
+    /// Runs every time the plugin is loaded, before `commands` and
+    /// `handlers` are asked for.
+    fn init(&mut self, _: &Context) {}
+
+    /// `Hook::register`s, with their metadata.
+    fn commands(&self) -> Hooks;
+
+    /// Message, join, part and event hooks.
+    fn handlers(&self) -> Hooks {
+        vec![]
+    }
+
+    /// Runs when the plugin is unloaded. Whatever's left in the plugin is
+    /// still there when it's loaded again.
+    fn shutdown(&mut self) {}
+}
+
+struct Entry {
+    plugin: Box<Plugin>,
+    /// While it's loaded, every hook it added.
+    loaded: Option<Vec<HookId>>,
+}
+
+impl Entry {
+    fn load(&mut self, db: &SqliteConnection) -> Hooks {
+        let name = self.plugin.name();
+        self.plugin.init(&Context::new(db, name));
+        let mut hooks = self.plugin.commands();
+        hooks.extend(self.plugin.handlers());
+        self.loaded = Some(hooks.iter().filter_map(Hook::added).collect());
+        hooks
+    }
+}
+
+/// Every plugin, loaded or not. Nothing here touches `HookStorage`: loading
+/// and unloading return the hooks to apply, like any other command does.
+#[derive(Clone)]
+pub struct Registry {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+impl Registry {
+    pub fn new(plugins: Vec<Box<Plugin>>) -> Self {
+        Registry {
+            entries: Arc::new(Mutex::new(plugins.into_iter()
+                .map(|p| Entry { plugin: p, loaded: None })
+                .collect())),
+        }
+    }
+
+    /// Loads everything that isn't loaded yet, in order.
+    pub fn load_all(&self, db: &SqliteConnection) -> Hooks {
+        let mut entries = self.entries.lock().unwrap();
+        entries.iter_mut()
+            .filter(|e| e.loaded.is_none())
+            .flat_map(|e| e.load(db))
+            .collect()
+    }
+
+    /// Each plugin's name, and whether it's loaded.
+    pub fn status(&self) -> Vec<(&'static str, bool)> {
+        self.entries.lock().unwrap().iter()
+            .map(|e| (e.plugin.name(), e.loaded.is_some()))
+            .collect()
+    }
+
+    pub fn load(&self, name: &str, db: &SqliteConnection) -> Result<Hooks, CommandError> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter_mut().find(|e| e.plugin.name() == name) {
+            Some(ref e) if e.loaded.is_some() => Err(CommandError::Usage(format!("{} is already loaded.", name))),
+            Some(e) => Ok(e.load(db)),
+            None => Err(CommandError::NotFound(format!("There's no {} plugin.", name))),
+        }
+    }
+
+    pub fn unload(&self, name: &str) -> Result<Hooks, CommandError> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter_mut().find(|e| e.plugin.name() == name) {
+            Some(e) => match e.loaded.take() {
+                Some(added) => {
+                    e.plugin.shutdown();
+                    Ok(added.into_iter().map(HookId::drop).collect())
+                },
+                None => Err(CommandError::Usage(format!("{} isn't loaded.", name))),
+            },
+            None => Err(CommandError::NotFound(format!("There's no {} plugin.", name))),
+        }
+    }
+
+    /// Unloads and loads a plugin in one go, so its commands are never
+    /// missing between the two.
+    pub fn reload(&self, name: &str, db: &SqliteConnection) -> Result<Hooks, CommandError> {
+        let mut hooks = self.unload(name)?;
+        hooks.extend(self.load(name, db)?);
+        Ok(hooks)
+    }
+}
+
+/// Counts its runs in state it keeps across loads.
+#[cfg(test)]
+struct Tally(Arc<Mutex<u32>>);
+
+#[cfg(test)]
+impl Plugin for Tally {
+    fn name(&self) -> &'static str {
+        "tally"
+    }
+
+    fn commands(&self) -> Hooks {
+        let n = self.0.clone();
+        vec![Hook::register("tally", |_| box move |e| {
+            let mut n = n.lock().unwrap();
+            *n += 1;
+            e.respond(format!("Counted {}.", *n));
+            Ok(vec![])
+        })]
+    }
+}
+
+#[test]
+fn plugin_registry() {
+    let db = ::db::test_db();
+    let (_core, _clock, mq) = ::messagequeue::test_queue();
+    let e = ::event::test_msg(&mq, "!tally");
+    let run = |hooks: &Hooks| {
+        for h in hooks.iter() {
+            if let Hook::AddCommand(_, _, ref c) = *h {
+                c(&e).unwrap();
+            }
+        }
+    };
+    let r = Registry::new(vec![box Tally(Arc::new(Mutex::new(0))) as Box<Plugin>]);
+    assert_eq!(r.status(), vec![("tally", false)]);
+
+    run(&r.load("tally", &db).unwrap());
+    assert_eq!(r.status(), vec![("tally", true)]);
+    assert_eq!(r.load("tally", &db).err(), Some(CommandError::Usage("tally is already loaded.".to_string())));
+    assert_eq!(r.load("nope", &db).err(), Some(CommandError::NotFound("There's no nope plugin.".to_string())));
+
+    let dropped = r.unload("tally").unwrap();
+    assert_eq!(dropped.len(), 1);
+    assert!(match dropped[0] { Hook::DropMessage(_) => true, _ => false });
+    assert_eq!(r.status(), vec![("tally", false)]);
+    assert_eq!(r.unload("tally").err(), Some(CommandError::Usage("tally isn't loaded.".to_string())));
+    assert_eq!(r.reload("tally", &db).err(), Some(CommandError::Usage("tally isn't loaded.".to_string())));
+
+    run(&r.load("tally", &db).unwrap());
+    let reloaded = r.reload("tally", &db).unwrap();
+    let kinds = reloaded.iter().map(|h| match *h {
+        Hook::DropMessage(_) => "drop",
+        Hook::AddCommand(..) => "add",
+        _ => "other",
+    }).collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["drop", "add"]);
+    run(&reloaded);
+
+    let said = mq.jobs().into_iter()
+        .map(|(_, _, job)| String::from_utf8_lossy(&job.message.as_bytes()).into_owned())
+        .collect::<Vec<_>>();
+    assert!(said.iter().any(|s| s.contains("Counted 3.")));
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+/// Maps command names and their aliases to whatever handles them. A name
+/// routes only when it's the whole command word, so `!pingpong` never
+/// reaches `ping`.
+pub struct Router<T> {
+    routes: HashMap<String, T>,
+    case_sensitive: bool,
+}
+
+impl<T> Router<T>
+    where T: Clone + PartialEq {
+    pub fn new(case_sensitive: bool) -> Self {
+        Router {
+            routes: HashMap::new(),
+            case_sensitive: case_sensitive,
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.case_sensitive {
+            name.to_string()
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    /// Routes `name` and `aliases` to `target`. If any of them is taken,
+    /// nothing is added and the first taken one is returned.
+    pub fn add(&mut self, name: &str, aliases: &[&str], target: T) -> Result<(), String> {
+        let mut keys = vec![self.key(name)];
+        keys.extend(aliases.iter().map(|a| self.key(a)));
+        for (i, k) in keys.iter().enumerate() {
+            if self.routes.contains_key(k) || keys[..i].contains(k) {
+                return Err(k.clone())
+            }
+        }
+        for k in keys.into_iter() {
+            self.routes.insert(k, target.clone());
+        }
+        Ok(())
+    }
+
+    /// Forgets every name that routes to `target`.
+    pub fn remove(&mut self, target: &T) {
+        let keys = self.routes.iter()
+            .filter(|&(_, t)| t == target)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for k in keys.iter() {
+            self.routes.remove(k);
+        }
+    }
+
+    /// Forgets one name, whatever it routes to.
+    pub fn remove_name(&mut self, name: &str) {
+        let k = self.key(name);
+        self.routes.remove(&k);
+    }
+
+    pub fn route(&self, cmd: &str) -> Option<&T> {
+        self.routes.get(&self.key(cmd))
+    }
+}
+
+#[test]
+fn router_exact() {
+    let mut r = Router::new(false);
+    r.add("ping", &[], 1).unwrap();
+    r.add("welcome", &[], 2).unwrap();
+    r.add("in", &[], 3).unwrap();
+    r.add("info", &[], 4).unwrap();
+    assert_eq!(r.route("ping"), Some(&1));
+    assert_eq!(r.route("pingpong"), None);
+    assert_eq!(r.route("pin"), None);
+    assert_eq!(r.route("welcomes"), None);
+    assert_eq!(r.route("in"), Some(&3));
+    assert_eq!(r.route("info"), Some(&4));
+}
+
+#[test]
+fn router_aliases() {
+    let mut r = Router::new(false);
+    r.add("commands", &["cmds", "list"], 1).unwrap();
+    assert_eq!(r.route("cmds"), Some(&1));
+    assert_eq!(r.route("list"), Some(&1));
+    assert_eq!(r.add("cmd", &["List"], 2), Err("list".to_string()));
+    assert_eq!(r.route("cmd"), None);
+    assert_eq!(r.add("twice", &["twice"], 3), Err("twice".to_string()));
+    assert_eq!(r.add("Commands", &[], 4), Err("commands".to_string()));
+    r.remove(&1);
+    assert_eq!(r.route("cmds"), None);
+    assert_eq!(r.route("commands"), None);
+    r.add("cmd", &["list"], 2).unwrap();
+    r.remove_name("List");
+    assert_eq!(r.route("list"), None);
+    assert_eq!(r.route("cmd"), Some(&2));
+}
+
+#[test]
+fn router_case() {
+    let mut r = Router::new(false);
+    r.add("Ping", &[], 1).unwrap();
+    assert_eq!(r.route("PING"), Some(&1));
+
+    let mut r = Router::new(true);
+    r.add("ping", &[], 1).unwrap();
+    r.add("Ping", &[], 2).unwrap();
+    assert_eq!(r.route("ping"), Some(&1));
+    assert_eq!(r.route("Ping"), Some(&2));
+    assert_eq!(r.route("PING"), None);
+}
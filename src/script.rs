@@ -0,0 +1,353 @@
+use args::MAX_DURATION;
+use diesel;
+use diesel::associations::HasTable;
+use event::Event;
+use hlua::{self,AsLua,Lua,LuaError};
+use libc::{self,c_void,size_t};
+use lua52_sys as ffi;
+use hooks::CommandError;
+use permissions::room_key;
+use std::cell::{Cell,RefCell};
+use std::collections::{HashMap,HashSet};
+use std::env;
+use std::ptr;
+use std::rc::Rc;
+use std::time::{Duration,Instant};
+
+pub mod models {
+    use ::db::{script_data,scripts};
+
+    #[derive(Queryable,Debug)]
+    pub struct Script {
+        id: i32,
+        pub channel: String,
+        pub name: String,
+        pub code: String,
+        pub author: String,
+    }
+
+    #[derive(Insertable,Debug)]
+    #[table_name="scripts"]
+    pub struct NewScript {
+        pub channel: String,
+        pub name: String,
+        pub code: String,
+        pub author: String,
+    }
+
+    #[derive(Queryable,Debug)]
+    pub struct Datum {
+        id: i32,
+        pub channel: String,
+        pub script: String,
+        pub key: String,
+        pub value: String,
+    }
+
+    #[derive(Insertable,Debug)]
+    #[table_name="script_data"]
+    pub struct NewDatum {
+        pub channel: String,
+        pub script: String,
+        pub key: String,
+        pub value: String,
+    }
+
+    impl Script {
+        /// The scripts called `n` in every room.
+        pub fn named(n: &str) -> ::diesel::helper_types::FindBy<
+                ::db::scripts::dsl::scripts,
+                ::db::scripts::dsl::name,
+                String> {
+            use ::db::scripts::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            scripts.filter(name.eq(n.to_string()))
+        }
+
+        pub fn in_room(room: &str) -> ::diesel::helper_types::FindBy<
+                ::db::scripts::dsl::scripts,
+                ::db::scripts::dsl::channel,
+                String> {
+            use ::db::scripts::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            scripts.filter(channel.eq(room.to_string()))
+        }
+    }
+
+    impl Datum {
+        pub fn in_room(room: &str) -> ::diesel::helper_types::FindBy<
+                ::db::script_data::dsl::script_data,
+                ::db::script_data::dsl::channel,
+                String> {
+            use ::db::script_data::dsl::*;
+
+            use diesel::FilterDsl;
+            use diesel::ExpressionMethods;
+
+            script_data.filter(channel.eq(room.to_string()))
+        }
+    }
+}
+
+use self::models::{Datum,NewDatum};
+
+/// How many messages one run may send.
+const MAX_MESSAGES: usize = 5;
+
+/// How many keys one script may keep.
+const MAX_KEYS: usize = 100;
+
+/// What a script asked for while it ran. None of it reaches the room or
+/// the database unless the script finishes without an error.
+#[derive(Default)]
+struct Effects {
+    said: Vec<(String, Option<Duration>)>,
+    store: HashMap<String, String>,
+    changed: HashSet<String>,
+    /// Why the script was refused something, which fails the run.
+    refused: Option<String>,
+}
+
+impl Effects {
+    fn refuse(&mut self, why: String) {
+        if self.refused.is_none() {
+            self.refused = Some(why);
+        }
+    }
+}
+
+fn limit(var: &str, default: u64) -> u64 {
+    env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// What the running script has left. Lua calls `on_step` before every
+/// instruction, which stops the script once it's out of steps or time,
+/// and asks `allocate` for every byte, which refuses any past `bytes`.
+#[derive(Clone, Copy)]
+struct Budget {
+    steps: u64,
+    until: Instant,
+    bytes: usize,
+    used: usize,
+}
+
+thread_local! {
+    static BUDGET: Cell<Option<Budget>> = Cell::new(None);
+}
+
+// Nothing in here may need dropping: `luaL_error` jumps straight back into Lua.
+extern "C" fn on_step(l: *mut ffi::lua_State, _: *mut ffi::lua_Debug) {
+    let over = BUDGET.with(|b| match b.get() {
+        Some(mut budget) => {
+            budget.steps = budget.steps.saturating_sub(1);
+            b.set(Some(budget));
+            budget.steps == 0 || Instant::now() > budget.until
+        },
+        None => false
+    });
+    if over {
+        unsafe {
+            ffi::luaL_error(l, b"it took too long\0".as_ptr() as *const _);
+        }
+    }
+}
+
+/// Lua's allocator: `realloc` and `free`, like the one it starts with, but
+/// failing anything that would take the script past its memory. Lua raises
+/// that as an error, so a single C call like `table.concat` can't get past
+/// it either.
+extern "C" fn allocate(_: *mut c_void, p: *mut c_void, old: size_t, new: size_t) -> *mut c_void {
+    // Without a block, `old` says what kind of object it's for.
+    let old = if p.is_null() { 0 } else { old as usize };
+    let new = new as usize;
+    let allowed = BUDGET.with(|b| match b.get() {
+        Some(mut budget) => {
+            let used = budget.used.saturating_sub(old) + new;
+            if new > old && used > budget.bytes {
+                return false
+            }
+            budget.used = used;
+            b.set(Some(budget));
+            true
+        },
+        None => true
+    });
+    unsafe {
+        if new == 0 {
+            libc::free(p);
+            ptr::null_mut()
+        } else if allowed {
+            libc::realloc(p, new)
+        } else {
+            ptr::null_mut()
+        }
+    }
+}
+
+/// A Lua state with only the base, string, table and math libraries, minus
+/// anything that loads code or makes huge strings in one go.
+fn sandbox<'lua>() -> Lua<'lua> {
+    let mut lua = Lua::new();
+    lua.open_base();
+    lua.open_string();
+    lua.open_table();
+    lua.open_math();
+    lua.execute::<()>("dofile, loadfile, load, loadstring, require, print, collectgarbage, string.rep = nil")
+        .expect("can't set up the script sandbox");
+    lua
+}
+
+/// A sandbox that only sees the event through the functions set here:
+///
+/// * `sender()`, `content()`: who ran the script, and what they said after
+///   its name.
+/// * `respond(msg)`, `respond_in(msg, seconds)`.
+/// * `get(key)`, `set(key, value)`: strings kept per script and room; `get`
+///   gives `""` for keys that were never set.
+fn engine<'lua>(sender: String, content: String, fx: Rc<RefCell<Effects>>) -> Lua<'lua> {
+    let mut lua = sandbox();
+
+    lua.set("sender", hlua::function0(move || sender.clone()));
+    lua.set("content", hlua::function0(move || content.clone()));
+
+    let f = fx.clone();
+    lua.set("respond", hlua::function1(move |msg: String| say(&f, msg, None)));
+    let f = fx.clone();
+    lua.set("respond_in", hlua::function2(move |msg: String, secs: f64| {
+        say(&f, msg, Some(Duration::from_secs(secs.max(0.0).min(MAX_DURATION as f64) as u64)))
+    }));
+
+    let f = fx.clone();
+    lua.set("get", hlua::function1(move |key: String| {
+        f.borrow().store.get(&key).cloned().unwrap_or_default()
+    }));
+    lua.set("set", hlua::function2(move |key: String, value: String| {
+        let mut fx = fx.borrow_mut();
+        if !fx.store.contains_key(&key) && fx.store.len() >= MAX_KEYS {
+            return fx.refuse(format!("it can't keep more than {} keys", MAX_KEYS))
+        }
+        fx.store.insert(key.clone(), value);
+        fx.changed.insert(key);
+    }));
+
+    lua
+}
+
+fn say(fx: &Rc<RefCell<Effects>>, msg: String, delay: Option<Duration>) {
+    let mut fx = fx.borrow_mut();
+    if fx.said.len() >= MAX_MESSAGES {
+        return fx.refuse(format!("it can't send more than {} messages", MAX_MESSAGES))
+    }
+    fx.said.push((msg, delay));
+}
+
+fn describe(e: LuaError) -> String {
+    match e {
+        LuaError::SyntaxError(s) | LuaError::ExecutionError(s) => s,
+        LuaError::ReadError(e) => e.to_string(),
+        LuaError::WrongType => "it returned something I can't use".to_string(),
+    }
+}
+
+/// Whether `code` would compile, without running it.
+pub fn check(code: &str) -> Result<(), String> {
+    let mut lua = Lua::new();
+    lua.open_base();
+    lua.set("code", code);
+    match lua.execute::<String>("local _, err = load(code) return err or ''") {
+        Ok(ref err) if err.is_empty() => Ok(()),
+        Ok(err) => Err(err),
+        Err(e) => Err(describe(e)),
+    }
+}
+
+/// Runs `code` in `lua` within the limits from `SCRIPT_MAX_OPERATIONS`
+/// (100,000 instructions), `SCRIPT_TIMEOUT_MS` (250) and
+/// `SCRIPT_MAX_MEMORY_KB` (4096).
+fn execute(lua: &mut Lua, code: &str) -> Result<(), String> {
+    let l = lua.as_lua().state_ptr();
+    let used = unsafe {
+        ffi::lua_gc(l, ffi::LUA_GCCOUNT, 0) as usize * 1024 + ffi::lua_gc(l, ffi::LUA_GCCOUNTB, 0) as usize
+    };
+    BUDGET.with(|b| b.set(Some(Budget {
+        steps: limit("SCRIPT_MAX_OPERATIONS", 100_000),
+        until: Instant::now() + Duration::from_millis(limit("SCRIPT_TIMEOUT_MS", 250)),
+        bytes: limit("SCRIPT_MAX_MEMORY_KB", 4096) as usize * 1024,
+        used: used,
+    })));
+    unsafe {
+        ffi::lua_setallocf(l, Some(allocate), ptr::null_mut());
+        ffi::lua_sethook(l, Some(on_step), ffi::LUA_MASKCOUNT, 1);
+    }
+    let ran = lua.execute::<()>(code).map_err(describe);
+    BUDGET.with(|b| b.set(None));
+    ran
+}
+
+/// Runs the script `name` for the event that invoked it. A script that
+/// fails is a usage error, since it's the room's own code.
+pub fn run(e: &Event, name: &str, code: &str) -> Result<(), CommandError> {
+    let room = room_key(&e.chatroom);
+    let store = e.load(Datum::in_room(&room))?.into_iter()
+        .filter(|d: &Datum| d.script == name)
+        .map(|d| (d.key, d.value))
+        .collect();
+    let fx = Rc::new(RefCell::new(Effects { store: store, ..Effects::default() }));
+
+    {
+        let mut lua = engine(e.sender.to_string(), e.content().to_string(), fx.clone());
+        let refused = |why: String| CommandError::Usage(format!("!{} stopped: {}", name, why));
+        execute(&mut lua, code).map_err(&refused)?;
+        if let Some(why) = fx.borrow_mut().refused.take() {
+            return Err(refused(why))
+        }
+    }
+
+    let fx = fx.borrow();
+    for &(ref msg, delay) in fx.said.iter() {
+        match delay {
+            Some(d) => e.respond_in(msg.as_str(), d)?,
+            None => e.respond(msg.as_str()),
+        };
+    }
+    for k in fx.changed.iter() {
+        use ::db::script_data::dsl::*;
+
+        e.execute(diesel::insert_or_replace(&NewDatum {
+            channel: room.clone(),
+            script: name.to_string(),
+            key: k.clone(),
+            value: fx.store[k].clone(),
+        }).into(script_data::table()))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn script_limits() {
+    assert!(check("respond(\"hi\")").is_ok());
+    assert!(check("respond(").is_err());
+
+    let fx = Rc::new(RefCell::new(Effects::default()));
+    let mut lua = engine("alice".to_string(), "".to_string(), fx.clone());
+    assert!(execute(&mut lua, "set(\"n\", sender() .. \"!\") respond(get(\"n\"))").is_ok());
+    assert_eq!(fx.borrow().said, vec![("alice!".to_string(), None)]);
+
+    assert!(execute(&mut lua, "while true do end").is_err());
+    assert!(execute(&mut lua, "local s = \"x\" while true do s = s .. s end").is_err());
+    assert!(execute(&mut lua, "require(\"os\")").is_err());
+    // One call that builds a string far bigger than the limit.
+    assert!(execute(&mut lua, "local s = \"x\" for i = 1, 18 do s = s .. s end \
+                               local t = {} for i = 1, 1000 do t[i] = s end \
+                               respond(table.concat(t))").is_err());
+    assert!(execute(&mut lua, "respond_in(\"later\", 1e300)").is_ok());
+    assert_eq!(fx.borrow().said.last(), Some(&("later".to_string(), Some(Duration::from_secs(MAX_DURATION)))));
+    assert!(execute(&mut lua, "for i = 1, 10 do respond(\"spam\") end").is_ok());
+    assert!(fx.borrow().refused.is_some());
+}
@@ -0,0 +1,121 @@
+use futures::{Future,Poll};
+use futures::future::{Either,Shared};
+use futures::sync::oneshot;
+use std::time::Duration;
+use tokio_core::reactor::{Handle,Timeout};
+
+/// How a task ended without finishing.
+#[derive(Debug, PartialEq)]
+pub enum Ended<E> {
+    Failed(E),
+    TimedOut,
+}
+
+/// Futures spawned on the reactor that give up after a while. Dropping
+/// the `Tasks` that spawned them drops any that are still running, so
+/// nothing outlives the connection it was started for.
+pub struct Tasks {
+    handle: Handle,
+    _alive: oneshot::Sender<()>,
+    dropped: Shared<oneshot::Receiver<()>>,
+}
+
+impl Tasks {
+    pub fn new(h: &Handle) -> Self {
+        let (tx, rx) = oneshot::channel();
+        Tasks {
+            handle: h.clone(),
+            _alive: tx,
+            dropped: rx.shared(),
+        }
+    }
+
+    /// Runs `f` until it finishes or `timeout` passes, then tells `done`
+    /// how it went. If the `Tasks` is dropped first, `done` is never called.
+    pub fn spawn<F, D>(&self, f: F, timeout: Duration, done: D)
+        where F: Future + 'static,
+              D: FnOnce(Result<F::Item, Ended<F::Error>>) + 'static {
+        let timer = match Timeout::new(timeout, &self.handle) {
+            Ok(t) => t,
+            Err(e) => {
+                warn!("can't start a timer, so not running the task: {}", e);
+                return
+            }
+        };
+        let task = f.select2(timer).then(move |r| {
+            done(match r {
+                Ok(Either::A((item, _))) => Ok(item),
+                Err(Either::A((e, _))) => Err(Ended::Failed(e)),
+                Ok(Either::B(_)) => Err(Ended::TimedOut),
+                Err(Either::B((e, _))) => {
+                    warn!("task timer failed: {}", e);
+                    Err(Ended::TimedOut)
+                },
+            });
+            Ok::<(), ()>(())
+        });
+        self.handle.spawn(Cancel {
+            task: task,
+            dropped: self.dropped.clone(),
+        });
+    }
+}
+
+/// Runs `task` unless `dropped` goes first.
+struct Cancel<F> {
+    task: F,
+    dropped: Shared<oneshot::Receiver<()>>,
+}
+
+impl<F> Future for Cancel<F>
+    where F: Future<Item = (), Error = ()> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.dropped.poll() {
+            Ok(::futures::Async::NotReady) => self.task.poll(),
+            _ => Ok(().into()),
+        }
+    }
+}
+
+#[cfg(test)]
+type Seen = ::std::rc::Rc<::std::cell::RefCell<Vec<Result<u32, Ended<&'static str>>>>>;
+
+#[test]
+fn tasks_finish_fail_and_time_out() {
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    let mut core = Core::new().unwrap();
+    let tasks = Tasks::new(&core.handle());
+    let seen = Seen::default();
+
+    let s = seen.clone();
+    tasks.spawn(future::ok::<u32, &'static str>(1), Duration::from_secs(5), move |r| s.borrow_mut().push(r));
+    let s = seen.clone();
+    tasks.spawn(future::err::<u32, &'static str>("no"), Duration::from_secs(5), move |r| s.borrow_mut().push(r));
+    let s = seen.clone();
+    tasks.spawn(future::empty::<u32, &'static str>(), Duration::from_millis(10), move |r| s.borrow_mut().push(r));
+
+    core.run(Timeout::new(Duration::from_millis(50), &core.handle()).unwrap()).unwrap();
+    assert_eq!(*seen.borrow(), vec![Ok(1), Err(Ended::Failed("no")), Err(Ended::TimedOut)]);
+}
+
+#[test]
+fn tasks_are_cancelled_when_dropped() {
+    use futures::future;
+    use tokio_core::reactor::Core;
+
+    let mut core = Core::new().unwrap();
+    let tasks = Tasks::new(&core.handle());
+    let seen = Seen::default();
+
+    let s = seen.clone();
+    tasks.spawn(future::empty::<u32, &'static str>(), Duration::from_millis(20), move |r| s.borrow_mut().push(r));
+    drop(tasks);
+
+    core.run(Timeout::new(Duration::from_millis(50), &core.handle()).unwrap()).unwrap();
+    assert!(seen.borrow().is_empty());
+}